@@ -0,0 +1,323 @@
+// Computer opponent. Moves are searched with negamax and alpha-beta pruning,
+// the weaker levels make mistakes on purpose.
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{Board, Cell, MoveError, Outcome, Player, Variant};
+
+// Heuristic scores of the big boards stay far below the win
+const WIN_SCORE: i32 = 1_000_000_000;
+const INFINITY: i32 = i32::MAX - 1; // Leaves room for the window below the best score
+
+// Medium level plays a random move instead of a searched one this often
+const MEDIUM_MISTAKE_CHANCE: f64 = 0.3;
+
+// Big boards are searched only around the pieces which are already placed
+const FULL_SEARCH_CELL_COUNT: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Perfect,
+}
+
+impl Difficulty {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "medium" => Some(Difficulty::Medium),
+            "perfect" => Some(Difficulty::Perfect),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Perfect => "perfect",
+        }
+    }
+}
+
+// Returns the index of the move for `Variant::play`, `None` if the game is
+// over. Error means that the engine has rejected a move it has listed as legal.
+pub fn choose_move(state: &Variant, difficulty: Difficulty) -> Result<Option<usize>, MoveError> {
+    let moves = state.legal_moves();
+    let mut rng = rand::thread_rng();
+
+    match difficulty {
+        // Doesn't miss a win in one move, but plays at random otherwise
+        Difficulty::Easy => {
+            Ok(winning_move(state, &moves).or_else(|| moves.choose(&mut rng).copied()))
+        }
+
+        Difficulty::Medium if rng.gen_bool(MEDIUM_MISTAKE_CHANCE) => {
+            Ok(winning_move(state, &moves).or_else(|| moves.choose(&mut rng).copied()))
+        }
+
+        Difficulty::Medium => best_move(state, 1),
+
+        Difficulty::Perfect => best_move(state, perfect_depth(state)),
+    }
+}
+
+// Depth of the search in plies after the move of the bot. The classic 3x3
+// game is solved completely, bigger ones are too large for that.
+fn perfect_depth(state: &Variant) -> u32 {
+    match state {
+        Variant::Classic(state) if state.board().cell_count() <= 9 => 9,
+        Variant::Classic(state) if state.board().cell_count() <= FULL_SEARCH_CELL_COUNT => 5,
+        Variant::Classic(_) => 2,
+        Variant::Ultimate(_) => 3,
+    }
+}
+
+fn winning_move(state: &Variant, moves: &[usize]) -> Option<usize> {
+    moves.iter().copied().find(|index| {
+        let mut child = state.clone();
+        child.play(*index).is_ok() && matches!(child.outcome(), Outcome::Win(..))
+    })
+}
+
+// Equally good moves are chosen at random, so the bot doesn't repeat itself
+fn best_move(state: &Variant, depth: u32) -> Result<Option<usize>, MoveError> {
+    let mut best_score = -INFINITY;
+    let mut best_moves = Vec::new();
+
+    for index in candidates(state) {
+        // Window starts just below the best score to still see the equal moves
+        let score = score_move(state, index, depth, best_score - 1, INFINITY)?;
+
+        if score > best_score {
+            best_score = score;
+            best_moves.clear();
+        }
+
+        if score == best_score {
+            best_moves.push(index);
+        }
+    }
+
+    Ok(best_moves.choose(&mut rand::thread_rng()).copied())
+}
+
+// Score of the position for the player on turn
+fn negamax(state: &Variant, depth: u32, mut alpha: i32, beta: i32) -> Result<i32, MoveError> {
+    let mut best = -INFINITY;
+
+    for index in candidates(state) {
+        let score = score_move(state, index, depth, alpha, beta)?;
+
+        best = best.max(score);
+        alpha = alpha.max(score);
+
+        if alpha >= beta {
+            break;
+        }
+    }
+
+    Ok(best)
+}
+
+// Score of the move for the player who makes it
+fn score_move(
+    state: &Variant,
+    index: usize,
+    depth: u32,
+    alpha: i32,
+    beta: i32,
+) -> Result<i32, MoveError> {
+    let player = state.turn();
+
+    let mut child = state.clone();
+    child.play(index)?;
+
+    let score = match child.outcome() {
+        // The sooner the win the better
+        Outcome::Win(..) => WIN_SCORE + depth as i32,
+        Outcome::Draw => 0,
+        Outcome::InProgress if depth == 0 => evaluate(&child, player),
+        Outcome::InProgress => -negamax(&child, depth - 1, -beta, -alpha)?,
+    };
+
+    Ok(score)
+}
+
+// Legal moves worth searching, the ones closer to the center go first
+fn candidates(state: &Variant) -> Vec<usize> {
+    let mut moves = state.legal_moves();
+
+    if let Variant::Classic(state) = state {
+        let board = state.board();
+
+        if board.cell_count() > FULL_SEARCH_CELL_COUNT {
+            let near: Vec<usize> = moves
+                .iter()
+                .copied()
+                .filter(|cell| has_neighbour_piece(board, *cell))
+                .collect();
+
+            if !near.is_empty() {
+                moves = near;
+            }
+        }
+
+        let center = board.size() as isize / 2;
+        moves.sort_by_key(|cell| {
+            let row = board.row(*cell) as isize;
+            let column = board.column(*cell) as isize;
+
+            (row - center).abs().max((column - center).abs())
+        });
+    }
+
+    moves
+}
+
+fn has_neighbour_piece(board: &Board, cell: usize) -> bool {
+    let size = board.size() as isize;
+    let (row, column) = (board.row(cell) as isize, board.column(cell) as isize);
+
+    (-1..=1).any(|rows| {
+        (-1..=1).any(|columns| {
+            let (row, column) = (row + rows, column + columns);
+
+            (0..size).contains(&row)
+                && (0..size).contains(&column)
+                && !board.is_empty_at((row * size + column) as usize)
+        })
+    })
+}
+
+// Heuristic score of an unfinished game for the given player
+fn evaluate(state: &Variant, player: Player) -> i32 {
+    match state {
+        Variant::Classic(state) => board_score(state.board(), player),
+
+        // Local boards only matter as the way to take the places on the big board
+        Variant::Ultimate(state) => {
+            let local: i32 = (0..9)
+                .filter(|board| state.local_outcome(*board) == Outcome::InProgress)
+                .map(|board| board_score(state.board(board), player))
+                .sum();
+
+            board_score(state.meta(), player) * 100 + local
+        }
+    }
+}
+
+// Every line where only one player has pieces is a threat of that player
+fn board_score(board: &Board, player: Player) -> i32 {
+    let size = board.size() as isize;
+    let win = board.rules().win as isize;
+
+    let mut score = 0;
+
+    for cell in 0..board.cell_count() {
+        let (row, column) = (board.row(cell) as isize, board.column(cell) as isize);
+
+        for (rows, columns) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+            let (last_row, last_column) = (row + rows * (win - 1), column + columns * (win - 1));
+
+            if !(0..size).contains(&last_row) || !(0..size).contains(&last_column) {
+                continue;
+            }
+
+            let (mut mine, mut theirs) = (0, 0);
+
+            for i in 0..win {
+                let index = (row + rows * i) * size + column + columns * i;
+
+                match board.cell(index as usize) {
+                    Cell::Taken(owner) if owner == player => mine += 1,
+                    Cell::Taken(_) => theirs += 1,
+                    Cell::Empty => {}
+                }
+            }
+
+            score += match (mine, theirs) {
+                (0, 0) => 0,
+                (mine, 0) => threat_weight(mine),
+                (0, theirs) => -threat_weight(theirs),
+                _ => 0,
+            };
+        }
+    }
+
+    score
+}
+
+// Long lines on the big boards must not outweigh the win
+fn threat_weight(pieces: u32) -> i32 {
+    10_i32.pow(pieces.min(5))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Mode;
+
+    fn classic(cells: &[usize]) -> Variant {
+        let mut state = Variant::new(Mode::default());
+
+        for cell in cells {
+            state.play(*cell).unwrap();
+        }
+
+        state
+    }
+
+    // Outcome of the whole game between the two levels, the first one plays X
+    fn play_game(first: Difficulty, second: Difficulty) -> Outcome {
+        let mut state = Variant::new(Mode::default());
+
+        while state.outcome() == Outcome::InProgress {
+            let difficulty = match state.turn() {
+                Player::First => first,
+                Player::Second => second,
+            };
+
+            let index = choose_move(&state, difficulty).unwrap().unwrap();
+            state.play(index).unwrap();
+        }
+
+        state.outcome()
+    }
+
+    #[test]
+    fn perfect_takes_the_win_in_one() {
+        // O threatens to finish its row too, but the own win comes first
+        let state = classic(&[0, 3, 1, 4]);
+
+        assert_eq!(choose_move(&state, Difficulty::Perfect), Ok(Some(2)));
+    }
+
+    #[test]
+    fn perfect_blocks_the_loss_in_one() {
+        let state = classic(&[0, 4, 1]);
+
+        assert_eq!(choose_move(&state, Difficulty::Perfect), Ok(Some(2)));
+    }
+
+    #[test]
+    fn perfect_never_loses_to_easy() {
+        for _ in 0..20 {
+            let outcome = play_game(Difficulty::Perfect, Difficulty::Easy);
+            assert!(!matches!(outcome, Outcome::Win(Player::Second, _)));
+
+            let outcome = play_game(Difficulty::Easy, Difficulty::Perfect);
+            assert!(!matches!(outcome, Outcome::Win(Player::First, _)));
+        }
+    }
+
+    #[test]
+    fn no_move_in_a_finished_game() {
+        let state = classic(&[0, 3, 1, 4, 2]);
+
+        assert_eq!(choose_move(&state, Difficulty::Perfect), Ok(None));
+    }
+}
@@ -0,0 +1,184 @@
+// Chess-like clocks of the games. The time of a side runs only on its turn,
+// and the increment is added after every move of it.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Player;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration, // Zero for sudden death
+}
+
+impl TimeControl {
+    // Minutes and seconds of the increment, e.g. "3+2"
+    pub fn from_name(name: &str) -> Option<Self> {
+        let (base, increment) = name.split_once('+')?;
+
+        Some(Self {
+            base: Duration::from_secs(base.parse::<u64>().ok()? * 60),
+            increment: Duration::from_secs(increment.parse().ok()?),
+        })
+    }
+
+    pub fn name(self) -> String {
+        format!("{}+{}", self.base.as_secs() / 60, self.increment.as_secs())
+    }
+}
+
+pub struct Clock {
+    control: TimeControl,
+    remaining: (Duration, Duration), // Of both players at the start of the current turn
+    turn: Player,
+    turn_started: Instant,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Self {
+            control,
+            remaining: (control.base, control.base),
+            turn: Player::First,
+            turn_started: Instant::now(),
+        }
+    }
+
+    // Clock saved before the restart, the time runs again from now
+    pub fn restore(control: TimeControl, remaining: (Duration, Duration), turn: Player) -> Self {
+        Self {
+            control,
+            remaining,
+            turn,
+            turn_started: Instant::now(),
+        }
+    }
+
+    pub fn control(&self) -> TimeControl {
+        self.control
+    }
+
+    pub fn remaining(&self, player: Player) -> Duration {
+        let remaining = match player {
+            Player::First => self.remaining.0,
+            Player::Second => self.remaining.1,
+        };
+
+        if player == self.turn {
+            remaining.saturating_sub(self.turn_started.elapsed())
+        } else {
+            remaining
+        }
+    }
+
+    // Stops the time of the player who has just moved and starts the time of the opponent
+    pub fn press(&mut self) {
+        match self.turn {
+            Player::First => self.remaining.0 += self.control.increment,
+            Player::Second => self.remaining.1 += self.control.increment,
+        }
+
+        self.switch();
+    }
+
+    // Passes the turn without the increment, e.g. when a move is taken back
+    pub fn switch(&mut self) {
+        let remaining = self.remaining(self.turn);
+
+        match self.turn {
+            Player::First => self.remaining.0 = remaining,
+            Player::Second => self.remaining.1 = remaining,
+        }
+
+        self.turn = self.turn.opponent();
+        self.turn_started = Instant::now();
+    }
+
+    // The player on turn has run out of time
+    pub fn is_flagged(&self) -> bool {
+        self.remaining(self.turn).is_zero()
+    }
+}
+
+// Minutes and seconds, e.g. 2:05
+pub fn format(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    const BLITZ: TimeControl = TimeControl {
+        base: Duration::from_secs(180),
+        increment: Duration::from_secs(2),
+    };
+
+    const TICK: Duration = Duration::from_millis(20);
+
+    #[test]
+    fn time_runs_only_on_turn() {
+        let clock = Clock::new(BLITZ);
+        thread::sleep(TICK);
+
+        assert!(clock.remaining(Player::First) < BLITZ.base);
+        assert_eq!(clock.remaining(Player::Second), BLITZ.base);
+    }
+
+    #[test]
+    fn press_adds_the_increment_and_passes_the_turn() {
+        let mut clock = Clock::new(BLITZ);
+        clock.press();
+
+        let remaining = clock.remaining(Player::First);
+        assert!(remaining > BLITZ.base && remaining <= BLITZ.base + BLITZ.increment);
+
+        thread::sleep(TICK);
+
+        assert_eq!(clock.remaining(Player::First), remaining);
+        assert!(clock.remaining(Player::Second) < BLITZ.base);
+    }
+
+    #[test]
+    fn switch_passes_the_turn_without_the_increment() {
+        let mut clock = Clock::new(BLITZ);
+        thread::sleep(TICK);
+        clock.switch();
+
+        let remaining = clock.remaining(Player::First);
+        assert!(remaining <= BLITZ.base - TICK);
+
+        clock.switch();
+
+        assert!(clock.remaining(Player::First) <= remaining);
+        assert!(clock.remaining(Player::Second) <= BLITZ.base);
+    }
+
+    #[test]
+    fn flag_falls_only_on_turn() {
+        let mut clock = Clock::restore(BLITZ, (Duration::ZERO, BLITZ.base), Player::Second);
+        assert!(!clock.is_flagged());
+
+        clock.switch();
+        assert!(clock.is_flagged());
+
+        // Opponent with plenty of time doesn't matter
+        let clock = Clock::restore(BLITZ, (BLITZ.base, Duration::ZERO), Player::Second);
+        assert!(clock.is_flagged());
+    }
+
+    #[test]
+    fn time_controls_and_durations_are_named() {
+        assert_eq!(TimeControl::from_name("3+2"), Some(BLITZ));
+        assert_eq!(TimeControl::from_name("3"), None);
+        assert_eq!(BLITZ.name(), "3+2");
+
+        assert_eq!(format(Duration::from_secs(125)), "2:05");
+    }
+}
@@ -0,0 +1,193 @@
+// Anti-aliased shapes drawn from their geometry, so they are sharp at any size
+// of the picture. Every pixel gets the color of the shape in the part which
+// the shape covers, the edges are mixed with what is under them.
+
+use std::f32::consts::PI;
+use std::ops::Range;
+
+use image::{Rgb, RgbImage};
+
+// Pixels are squares of the side 1 with the centers at the halves
+pub type Point = (f32, f32);
+
+const SCRATCH_STRANDS: u32 = 4; // Thin strokes which make up a scratch
+
+pub fn fill_rect(
+    canvas: &mut RgbImage,
+    left: f32,
+    top: f32,
+    width: f32,
+    height: f32,
+    color: Rgb<u8>,
+) {
+    let (right, bottom) = (left + width, top + height);
+
+    for y in rows(canvas, top, bottom) {
+        let coverage_y = overlap(y, top, bottom);
+
+        for x in columns(canvas, left, right) {
+            paint(canvas, x, y, color, coverage_y * overlap(x, left, right));
+        }
+    }
+}
+
+// Line of the given width with the round ends
+pub fn stroke_segment(canvas: &mut RgbImage, from: Point, to: Point, width: f32, color: Rgb<u8>) {
+    let radius = width / 2.0;
+
+    let (left, right) = (from.0.min(to.0) - radius, from.0.max(to.0) + radius);
+    let (top, bottom) = (from.1.min(to.1) - radius, from.1.max(to.1) + radius);
+
+    for y in rows(canvas, top, bottom) {
+        for x in columns(canvas, left, right) {
+            let center = (x as f32 + 0.5, y as f32 + 0.5);
+            let distance = distance_to_segment(center, from, to);
+
+            paint(canvas, x, y, color, coverage(radius - distance));
+        }
+    }
+}
+
+// Ring which line goes along the circle of the given radius
+pub fn stroke_circle(
+    canvas: &mut RgbImage,
+    center: Point,
+    radius: f32,
+    width: f32,
+    color: Rgb<u8>,
+) {
+    let outer = radius + width / 2.0;
+
+    for y in rows(canvas, center.1 - outer, center.1 + outer) {
+        for x in columns(canvas, center.0 - outer, center.0 + outer) {
+            let (dx, dy) = (x as f32 + 0.5 - center.0, y as f32 + 0.5 - center.1);
+            let distance = ((dx * dx + dy * dy).sqrt() - radius).abs();
+
+            paint(canvas, x, y, color, coverage(width / 2.0 - distance));
+        }
+    }
+}
+
+// Two diagonals of the square with the given center and half of the side
+pub fn stroke_cross(canvas: &mut RgbImage, center: Point, half: f32, width: f32, color: Rgb<u8>) {
+    let (x, y) = center;
+
+    stroke_segment(
+        canvas,
+        (x - half, y - half),
+        (x + half, y + half),
+        width,
+        color,
+    );
+    stroke_segment(
+        canvas,
+        (x - half, y + half),
+        (x + half, y - half),
+        width,
+        color,
+    );
+}
+
+// Line drawn by hand: thin strokes side by side, which bend together and
+// jitter a little each, and come closer at the ends. The same ends always
+// give the same scratch, so the pictures stay the same for the cache.
+pub fn stroke_scratch(canvas: &mut RgbImage, from: Point, to: Point, width: f32, color: Rgb<u8>) {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length = (dx * dx + dy * dy).sqrt();
+
+    if length == 0.0 {
+        stroke_segment(canvas, from, to, width, color);
+        return;
+    }
+
+    let normal = (-dy / length, dx / length);
+    let steps = ((length / width) as u32).max(4);
+
+    for strand in 0..SCRATCH_STRANDS {
+        let spread = (strand as f32 / (SCRATCH_STRANDS - 1) as f32 - 0.5) * width * 0.6;
+
+        let points: Vec<Point> = (0..=steps)
+            .map(|step| {
+                let t = step as f32 / steps as f32;
+
+                let bend = width * 0.5 * (t * PI * 1.5 + 0.7).sin() * (t * PI).sin();
+                let jitter = width * 0.08 * noise(strand * 7_919 + step);
+                let offset = bend + jitter + spread * (t * PI).sin().sqrt().max(0.3);
+
+                (
+                    from.0 + dx * t + normal.0 * offset,
+                    from.1 + dy * t + normal.1 * offset,
+                )
+            })
+            .collect();
+
+        for pair in points.windows(2) {
+            stroke_segment(canvas, pair[0], pair[1], width * 0.4, color);
+        }
+    }
+}
+
+// Color mixed into the pixel by the part of the pixel it covers
+fn paint(canvas: &mut RgbImage, x: u32, y: u32, color: Rgb<u8>, coverage: f32) {
+    if coverage <= 0.0 {
+        return;
+    }
+
+    let pixel = canvas.get_pixel_mut(x, y);
+
+    for (channel, value) in pixel.0.iter_mut().zip(color.0) {
+        *channel = (*channel as f32 + (value as f32 - *channel as f32) * coverage).round() as u8;
+    }
+}
+
+// Part of the pixel inside the shape, the pixel is about a half inside at the edge
+fn coverage(depth: f32) -> f32 {
+    (depth + 0.5).clamp(0.0, 1.0)
+}
+
+// Part of the pixel between the two lines along the same axis
+fn overlap(pixel: u32, from: f32, to: f32) -> f32 {
+    let pixel = pixel as f32;
+    (to.min(pixel + 1.0) - from.max(pixel)).clamp(0.0, 1.0)
+}
+
+fn rows(canvas: &RgbImage, from: f32, to: f32) -> Range<u32> {
+    clip(from, to, canvas.height())
+}
+
+fn columns(canvas: &RgbImage, from: f32, to: f32) -> Range<u32> {
+    clip(from, to, canvas.width())
+}
+
+// Pixels touched by the span, inside of the canvas
+fn clip(from: f32, to: f32, size: u32) -> Range<u32> {
+    let from = from.floor().max(0.0) as u32;
+    let to = (to.ceil().max(0.0) as u32).min(size);
+
+    from.min(to)..to
+}
+
+// Number from -1 to 1 which looks random, but is always the same for the seed
+fn noise(seed: u32) -> f32 {
+    let mut x = seed.wrapping_mul(2_654_435_761) ^ 0x9e37_79b9;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+
+    (x % 2_001) as f32 / 1_000.0 - 1.0
+}
+
+fn distance_to_segment(point: Point, from: Point, to: Point) -> f32 {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length = dx * dx + dy * dy;
+
+    // Position of the nearest point of the segment, from 0 at the start to 1 at the end
+    let t = if length == 0.0 {
+        0.0
+    } else {
+        (((point.0 - from.0) * dx + (point.1 - from.1) * dy) / length).clamp(0.0, 1.0)
+    };
+
+    let (x, y) = (from.0 + dx * t - point.0, from.1 + dy * t - point.1);
+    (x * x + y * y).sqrt()
+}
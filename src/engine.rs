@@ -0,0 +1,526 @@
+// Rules of the game. Nothing in this module knows about Discord, so it can
+// be driven from the bot handlers as well as from plain unit tests.
+
+pub mod ultimate;
+
+use serde::{Deserialize, Serialize};
+
+use ultimate::UltimateState;
+
+pub const MIN_SIZE: usize = 3;
+pub const MAX_SIZE: usize = 15;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct Rules {
+    pub size: usize, // Board is `size` x `size` cells
+    pub win: usize,  // Count of pieces in a row needed for a win
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self { size: 3, win: 3 }
+    }
+}
+
+impl Rules {
+    pub fn new(size: usize, win: usize) -> Option<Self> {
+        if (MIN_SIZE..=MAX_SIZE).contains(&size) && (MIN_SIZE..=size).contains(&win) {
+            Some(Self { size, win })
+        } else {
+            None
+        }
+    }
+
+    // Five in a row is the gomoku standard, so bigger boards don't ask for more
+    pub fn with_default_win(size: usize) -> Option<Self> {
+        Self::new(size, size.min(5))
+    }
+
+    pub fn is_classic(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub enum Player {
+    First,  // Plays with X and always moves first
+    Second, // Plays with O
+}
+
+impl Player {
+    pub fn opponent(self) -> Self {
+        match self {
+            Player::First => Player::Second,
+            Player::Second => Player::First,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Cell {
+    #[default]
+    Empty,
+    Taken(Player),
+}
+
+// Directions of the lines are chosen so that `start` is always the cell with
+// the smallest index
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Horizontal,
+    Vertical,
+    Diagonal,     // Left to right
+    AntiDiagonal, // Right to left
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Horizontal,
+        Direction::Vertical,
+        Direction::Diagonal,
+        Direction::AntiDiagonal,
+    ];
+
+    // (row, column) offset of the next cell in the line
+    fn offset(self) -> (isize, isize) {
+        match self {
+            Direction::Horizontal => (0, 1),
+            Direction::Vertical => (1, 0),
+            Direction::Diagonal => (1, 1),
+            Direction::AntiDiagonal => (1, -1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Line {
+    pub start: usize,
+    pub end: usize,
+    pub direction: Direction,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+// Selected place on the board of the player on turn
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CursorPos {
+    Cell(usize),             // Classic board
+    Board(usize),            // Ultimate board, choosing a local board
+    LocalCell(usize, usize), // Ultimate board, choosing a cell of the local board
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Move {
+    pub player: Player,
+    pub cell: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    InProgress,
+    Win(Player, Line),
+    Draw,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveError {
+    OutOfBounds,
+    Occupied,
+    WrongBoard, // Ultimate game only
+    GameOver,
+}
+
+// Cells are numbered row by row, e.g. for the 3x3 board:
+// 0 1 2
+// 3 4 5
+// 6 7 8
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Board {
+    rules: Rules,
+    cells: Vec<Cell>,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new(Rules::default())
+    }
+}
+
+impl Board {
+    pub fn new(rules: Rules) -> Self {
+        Self {
+            rules,
+            cells: vec![Cell::Empty; rules.size * rules.size],
+        }
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    pub fn size(&self) -> usize {
+        self.rules.size
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn center(&self) -> usize {
+        let middle = self.size() / 2;
+        middle * self.size() + middle
+    }
+
+    pub fn row(&self, cell: usize) -> usize {
+        cell / self.size()
+    }
+
+    pub fn column(&self, cell: usize) -> usize {
+        cell % self.size()
+    }
+
+    pub fn cell(&self, cell: usize) -> Cell {
+        self.cells[cell]
+    }
+
+    pub fn is_empty_at(&self, cell: usize) -> bool {
+        self.cells[cell] == Cell::Empty
+    }
+
+    pub fn empty_cells(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.cells.len()).filter(|cell| self.is_empty_at(*cell))
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.cells.iter().all(|cell| *cell != Cell::Empty)
+    }
+
+    // Cell next to the given one, `None` at the edge of the board
+    pub fn neighbour(&self, cell: usize, step: Step) -> Option<usize> {
+        let offset = match step {
+            Step::Left => (0, -1),
+            Step::Down => (1, 0),
+            Step::Up => (-1, 0),
+            Step::Right => (0, 1),
+        };
+
+        self.offset(cell, offset)
+    }
+
+    fn offset(&self, cell: usize, (rows, columns): (isize, isize)) -> Option<usize> {
+        let row = self.row(cell).checked_add_signed(rows)?;
+        let column = self.column(cell).checked_add_signed(columns)?;
+
+        if row < self.size() && column < self.size() {
+            Some(row * self.size() + column)
+        } else {
+            None
+        }
+    }
+
+    // The longest run of equal pieces through the cell in the given direction
+    fn run_through(&self, cell: usize, direction: Direction) -> (Line, usize) {
+        let (rows, columns) = direction.offset();
+        let piece = self.cells[cell];
+
+        let (mut start, mut end, mut length) = (cell, cell, 1);
+
+        while let Some(prev) = self.offset(start, (-rows, -columns)) {
+            if self.cells[prev] != piece {
+                break;
+            }
+            start = prev;
+            length += 1;
+        }
+
+        while let Some(next) = self.offset(end, (rows, columns)) {
+            if self.cells[next] != piece {
+                break;
+            }
+            end = next;
+            length += 1;
+        }
+
+        let line = Line {
+            start,
+            end,
+            direction,
+        };
+
+        (line, length)
+    }
+
+    // Only lines through the last move can be new, so there is no need to scan
+    // the whole board after every move
+    fn winning_line_through(&self, cell: usize) -> Option<Line> {
+        if self.is_empty_at(cell) {
+            return None;
+        }
+
+        Direction::ALL
+            .into_iter()
+            .map(|direction| self.run_through(cell, direction))
+            .find(|(_, length)| *length >= self.rules.win)
+            .map(|(line, _)| line)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameState {
+    board: Board,
+    turn: Player,
+    outcome: Outcome,
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new(Rules::default())
+    }
+}
+
+impl GameState {
+    pub fn new(rules: Rules) -> Self {
+        Self {
+            board: Board::new(rules),
+            turn: Player::First,
+            outcome: Outcome::InProgress,
+        }
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn turn(&self) -> Player {
+        self.turn
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        self.outcome
+    }
+
+    pub fn legal_moves(&self) -> Vec<usize> {
+        if self.outcome != Outcome::InProgress {
+            return Vec::new();
+        }
+
+        self.board.empty_cells().collect()
+    }
+
+    // Puts a piece of the player on turn and passes the turn if the game goes on
+    pub fn play(&mut self, cell: usize) -> Result<Move, MoveError> {
+        if self.outcome != Outcome::InProgress {
+            return Err(MoveError::GameOver);
+        }
+
+        if cell >= self.board.cell_count() {
+            return Err(MoveError::OutOfBounds);
+        }
+
+        if !self.board.is_empty_at(cell) {
+            return Err(MoveError::Occupied);
+        }
+
+        let player = self.turn;
+        self.board.cells[cell] = Cell::Taken(player);
+
+        self.outcome = if let Some(line) = self.board.winning_line_through(cell) {
+            Outcome::Win(player, line)
+        } else if self.board.is_full() {
+            Outcome::Draw
+        } else {
+            self.turn = player.opponent();
+            Outcome::InProgress
+        };
+
+        Ok(Move { player, cell })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub enum Mode {
+    Classic(Rules),
+    Ultimate,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Classic(Rules::default())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Variant {
+    Classic(GameState),
+    Ultimate(UltimateState),
+}
+
+impl Variant {
+    pub fn new(mode: Mode) -> Self {
+        match mode {
+            Mode::Classic(rules) => Variant::Classic(GameState::new(rules)),
+            Mode::Ultimate => Variant::Ultimate(UltimateState::new()),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        match self {
+            Variant::Classic(state) => Mode::Classic(state.board().rules()),
+            Variant::Ultimate(_) => Mode::Ultimate,
+        }
+    }
+
+    pub fn turn(&self) -> Player {
+        match self {
+            Variant::Classic(state) => state.turn(),
+            Variant::Ultimate(state) => state.turn(),
+        }
+    }
+
+    // Winning line of the ultimate game lies on the big board
+    pub fn outcome(&self) -> Outcome {
+        match self {
+            Variant::Classic(state) => state.outcome(),
+            Variant::Ultimate(state) => state.outcome(),
+        }
+    }
+
+    // Moves are numbered the same way as the cells of `Move`
+    pub fn legal_moves(&self) -> Vec<usize> {
+        match self {
+            Variant::Classic(state) => state.legal_moves(),
+            Variant::Ultimate(state) => state.legal_moves(),
+        }
+    }
+
+    pub fn play(&mut self, index: usize) -> Result<Move, MoveError> {
+        match self {
+            Variant::Classic(state) => state.play(index),
+            Variant::Ultimate(state) => {
+                let (board, cell) = ultimate::split_index(index);
+                state.play(board, cell)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Moves of both players one after another, every one has to be legal
+    fn play_all(rules: Rules, cells: &[usize]) -> GameState {
+        let mut state = GameState::new(rules);
+
+        for cell in cells {
+            state.play(*cell).unwrap();
+        }
+
+        state
+    }
+
+    fn win(start: usize, end: usize, direction: Direction) -> Outcome {
+        let line = Line {
+            start,
+            end,
+            direction,
+        };
+
+        Outcome::Win(Player::First, line)
+    }
+
+    #[test]
+    fn wins_in_every_direction() {
+        let rules = Rules::default();
+
+        let state = play_all(rules, &[0, 3, 1, 4, 2]);
+        assert_eq!(state.outcome(), win(0, 2, Direction::Horizontal));
+
+        let state = play_all(rules, &[0, 1, 3, 2, 6]);
+        assert_eq!(state.outcome(), win(0, 6, Direction::Vertical));
+
+        let state = play_all(rules, &[0, 1, 4, 2, 8]);
+        assert_eq!(state.outcome(), win(0, 8, Direction::Diagonal));
+
+        let state = play_all(rules, &[2, 0, 4, 1, 6]);
+        assert_eq!(state.outcome(), win(2, 6, Direction::AntiDiagonal));
+    }
+
+    #[test]
+    fn lines_do_not_wrap_around_the_edges() {
+        let rules = Rules::new(4, 3).unwrap();
+
+        // The end of the first row and the start of the second one
+        let state = play_all(rules, &[3, 8, 4, 9, 5]);
+        assert_eq!(state.outcome(), Outcome::InProgress);
+
+        // Cells one row and one column apart, but across the right edge
+        let state = play_all(rules, &[3, 0, 8, 1, 13]);
+        assert_eq!(state.outcome(), Outcome::InProgress);
+
+        // Cells one row down and one column to the left, across the left edge
+        let state = play_all(rules, &[4, 0, 7, 1, 10]);
+        assert_eq!(state.outcome(), Outcome::InProgress);
+    }
+
+    #[test]
+    fn shorter_runs_win_on_bigger_boards() {
+        let rules = Rules::new(5, 4).unwrap();
+
+        let state = play_all(rules, &[0, 10, 1, 11, 2, 12]);
+        assert_eq!(state.outcome(), Outcome::InProgress);
+
+        let state = play_all(rules, &[0, 10, 1, 11, 2, 12, 3]);
+        assert_eq!(state.outcome(), win(0, 3, Direction::Horizontal));
+
+        let state = play_all(rules, &[6, 0, 12, 1, 18, 2, 24]);
+        assert_eq!(state.outcome(), win(6, 24, Direction::Diagonal));
+    }
+
+    #[test]
+    fn full_board_without_a_line_is_a_draw() {
+        let state = play_all(Rules::default(), &[0, 1, 2, 4, 3, 5, 7, 6, 8]);
+
+        assert_eq!(state.outcome(), Outcome::Draw);
+        assert!(state.legal_moves().is_empty());
+    }
+
+    #[test]
+    fn illegal_moves_are_rejected() {
+        let mut state = play_all(Rules::default(), &[0]);
+
+        assert_eq!(state.play(0), Err(MoveError::Occupied));
+        assert_eq!(state.play(9), Err(MoveError::OutOfBounds));
+        assert_eq!(state.turn(), Player::Second);
+
+        let mut state = play_all(Rules::default(), &[0, 3, 1, 4, 2]);
+        assert_eq!(state.play(5), Err(MoveError::GameOver));
+    }
+
+    #[test]
+    fn ultimate_move_into_a_finished_board_frees_the_next_one() {
+        let mut state = UltimateState::new();
+
+        state.play(0, 1).unwrap();
+        assert_eq!(state.active(), Some(1));
+        assert_eq!(state.play(3, 0), Err(MoveError::WrongBoard));
+
+        for (board, cell) in [(1, 0), (0, 2), (2, 0), (0, 0)] {
+            state.play(board, cell).unwrap();
+        }
+
+        assert_eq!(state.local_outcome(0), win(0, 2, Direction::Horizontal));
+        assert_eq!(state.meta().cell(0), Cell::Taken(Player::First));
+
+        // The last move points at the won board, so any other board will do
+        assert_eq!(state.active(), None);
+        assert_eq!(state.play(0, 4), Err(MoveError::WrongBoard));
+        assert!(state.play(5, 5).is_ok());
+    }
+}
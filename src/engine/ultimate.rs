@@ -0,0 +1,131 @@
+// Ultimate tic-tac-toe: a 3x3 board of classic 3x3 boards. A local board is
+// won like a classic one and becomes a piece of the winner on the big board.
+// The cell you play in decides the local board where the opponent must play
+// next, unless that board is already finished.
+
+use super::{Board, Cell, Move, MoveError, Outcome, Player};
+
+pub const BOARD_COUNT: usize = 9;
+pub const LOCAL_CELL_COUNT: usize = 9;
+
+// Moves are numbered board by board, so the cell index of a `Move` is
+// `board * LOCAL_CELL_COUNT + cell`
+pub fn split_index(index: usize) -> (usize, usize) {
+    (index / LOCAL_CELL_COUNT, index % LOCAL_CELL_COUNT)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UltimateState {
+    boards: Vec<Board>,
+    results: Vec<Outcome>, // Outcome of every local board
+    meta: Board,           // Big board with the won local boards
+    active: Option<usize>, // Local board to play in, `None` for any
+    turn: Player,
+    outcome: Outcome,
+}
+
+impl Default for UltimateState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UltimateState {
+    pub fn new() -> Self {
+        Self {
+            boards: vec![Board::default(); BOARD_COUNT],
+            results: vec![Outcome::InProgress; BOARD_COUNT],
+            meta: Board::default(),
+            active: None,
+            turn: Player::First,
+            outcome: Outcome::InProgress,
+        }
+    }
+
+    pub fn board(&self, board: usize) -> &Board {
+        &self.boards[board]
+    }
+
+    pub fn local_outcome(&self, board: usize) -> Outcome {
+        self.results[board]
+    }
+
+    pub fn meta(&self) -> &Board {
+        &self.meta
+    }
+
+    pub fn active(&self) -> Option<usize> {
+        self.active
+    }
+
+    pub fn turn(&self) -> Player {
+        self.turn
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        self.outcome
+    }
+
+    pub fn can_play_in(&self, board: usize) -> bool {
+        self.outcome == Outcome::InProgress
+            && self.results[board] == Outcome::InProgress
+            && (self.active.is_none() || self.active == Some(board))
+    }
+
+    pub fn legal_moves(&self) -> Vec<usize> {
+        (0..BOARD_COUNT)
+            .filter(|board| self.can_play_in(*board))
+            .flat_map(|board| {
+                self.boards[board]
+                    .empty_cells()
+                    .map(move |cell| board * LOCAL_CELL_COUNT + cell)
+            })
+            .collect()
+    }
+
+    pub fn play(&mut self, board: usize, cell: usize) -> Result<Move, MoveError> {
+        if self.outcome != Outcome::InProgress {
+            return Err(MoveError::GameOver);
+        }
+
+        if board >= BOARD_COUNT || cell >= LOCAL_CELL_COUNT {
+            return Err(MoveError::OutOfBounds);
+        }
+
+        if !self.can_play_in(board) {
+            return Err(MoveError::WrongBoard);
+        }
+
+        if !self.boards[board].is_empty_at(cell) {
+            return Err(MoveError::Occupied);
+        }
+
+        let player = self.turn;
+        let local = &mut self.boards[board];
+        local.cells[cell] = Cell::Taken(player);
+
+        self.results[board] = if let Some(line) = local.winning_line_through(cell) {
+            self.meta.cells[board] = Cell::Taken(player);
+            Outcome::Win(player, line)
+        } else if local.is_full() {
+            Outcome::Draw
+        } else {
+            Outcome::InProgress
+        };
+
+        self.outcome = if let Some(line) = self.meta.winning_line_through(board) {
+            Outcome::Win(player, line)
+        } else if !self.results.contains(&Outcome::InProgress) {
+            Outcome::Draw
+        } else {
+            self.turn = player.opponent();
+            self.active = (self.results[cell] == Outcome::InProgress).then_some(cell);
+            Outcome::InProgress
+        };
+
+        Ok(Move {
+            player,
+            cell: board * LOCAL_CELL_COUNT + cell,
+        })
+    }
+}
@@ -0,0 +1,53 @@
+// Errors of the handlers of the commands and the buttons. The user sees the
+// text of the error, the details go to the log.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Discord(Box<serenity::Error>), // Boxed, it is much larger than the rest
+    NotInGame,                     // Button of the game which the user doesn't play
+    GameFinished,                  // Button of the game which has ended already
+    NotYourTurn,                   // Board button of the player who waits for the opponent
+    StaleButton,                   // Button of an outdated panel of the game
+    Busy,                          // Too many pictures are waiting to be drawn
+    InvalidMoves,                  // Moves of the game which can't be replayed
+    BotFailed,                     // Search of the bot has crashed or found no move
+    RenderFailed,                  // Drawing of the picture has crashed
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Discord(_) => write!(f, "Discord has failed to handle the request, try again."),
+            Error::NotInGame => write!(f, "You are not in this game."),
+            Error::GameFinished => write!(f, "This game has already finished."),
+            Error::NotYourTurn => write!(f, "It's not your turn."),
+            Error::StaleButton => write!(
+                f,
+                "These buttons are outdated, use the latest controls of the game."
+            ),
+            Error::Busy => write!(f, "The bot is busy right now, try again in a moment."),
+            Error::InvalidMoves => write!(f, "The moves of this game can't be replayed."),
+            Error::BotFailed => write!(f, "The bot has failed to make its move."),
+            Error::RenderFailed => write!(f, "The bot has failed to draw the picture, try again."),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Discord(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<serenity::Error> for Error {
+    fn from(err: serenity::Error) -> Self {
+        Error::Discord(Box::new(err))
+    }
+}
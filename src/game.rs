@@ -1,172 +1,587 @@
-use std::io::{BufWriter, Cursor};
+use std::iter;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use image::{ColorType, ImageBuffer, ImageOutputFormat, Rgb, Rgba};
-use imageproc::drawing::{draw_filled_rect_mut, Canvas};
-use imageproc::rect::Rect;
-
-use serenity::all::{ButtonStyle, CommandInteraction, ComponentInteraction};
+use serenity::all::{
+    ButtonStyle, CommandInteraction, CommandOptionType, ComponentInteraction, Mentionable,
+};
 use serenity::builder::{
-    CreateActionRow, CreateAttachment, CreateButton, CreateCommand, CreateEmbed, CreateEmbedAuthor,
-    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    CreateActionRow, CreateAttachment, CreateButton, CreateCommand, CreateCommandOption,
+    CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter, CreateInteractionResponse,
+    CreateInteractionResponseFollowup, CreateInteractionResponseMessage, CreateMessage,
     EditInteractionResponse, EditMessage,
 };
-use serenity::http::Http;
-use serenity::model::prelude::{Message, UserId};
+use serenity::http::{Http, HttpError, StatusCode};
+use serenity::model::prelude::{GuildId, Member, Message, Permissions, User, UserId};
 use serenity::prelude::Context;
 
 use tokio::sync::Mutex;
 
-const BACKGROUND: Rgb<u8> = Rgb([42, 44, 47]);
-const GRAY: Rgb<u8> = Rgb([232, 232, 232]);
-const RED: Rgb<u8> = Rgb([196, 57, 57]);
-
-const CELLS: [(i32, i32); 9] = [
-    (0, 0),
-    (100, 0),
-    (200, 0),
-    (0, 100),
-    (100, 100),
-    (200, 100),
-    (0, 200),
-    (100, 200),
-    (200, 200),
-];
+use crate::ai::{self, Difficulty};
+use crate::clock::{self, Clock, TimeControl};
+use crate::engine::{
+    Cell, CursorPos, Mode, Move, MoveError, Outcome, Player, Rules, Step, Variant, MAX_SIZE,
+    MIN_SIZE,
+};
+use crate::error::{Error, Result};
+use crate::input::Input;
+use crate::interaction::{option_value, reply_ephemeral};
+use crate::leaderboard::{Leaderboards, Pins};
+use crate::notation;
+use crate::pool::RenderPool;
+use crate::queue::{Policy, QueueKey, Queues};
+use crate::rating::{self, Change};
+use crate::render::{Renderer, Scene};
+use crate::restore::{SavedSession, SavedSessions};
+use crate::sessions::Sessions;
+use crate::stats::{self, Ending, GameRecord, GameResult, Stats};
+use crate::theme::ThemeChoices;
+
+const STATS_PATH: &str = "./stats.jsonl";
+const PINS_PATH: &str = "./leaderboards.json";
+const SESSIONS_PATH: &str = "./sessions.json";
+const THEME_CHOICES_PATH: &str = "./theme_choices.json";
+const QUEUE_POLICIES_PATH: &str = "./queue_policies.json";
+const HEAD_TO_HEAD_LIMIT: usize = 10; // Opponents shown by the `/stats` command
+
+const BLANK_LABEL: &str = "\u{200b}"; // Discord doesn't allow buttons without a label
+
+const MATCHMAKING_INTERVAL: Duration = Duration::from_secs(5);
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const LEADERBOARD_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const PERIOD_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60); // Old games drop out of the week and month tables
+const RENDER_METRICS_INTERVAL: Duration = Duration::from_secs(300);
+
+const BOARD_SCALE: u32 = 2; // Boards are sharp on the HiDPI screens, the replays stay smaller
+
+const CHALLENGE_TIMEOUT: u64 = 60; // Seconds, if the challenger hasn't chosen another one
+const MIN_CHALLENGE_TIMEOUT: u64 = 15;
+const MAX_CHALLENGE_TIMEOUT: u64 = 600;
 
 #[derive(Default)]
 pub struct Game {
-    x_image: ImageBuffer<Rgb<u8>, Vec<u8>>,
-    o_image: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    render: RenderPool,
+
+    queues: Mutex<Queues<WaitingPlayer>>,
+    background_tasks: AtomicBool, // Matchmaking and refreshing of the pinned leaderboards
+    challenges: Arc<Mutex<Vec<Challenge>>>, // Shared with the timers of the challenges
 
-    horizontal_scratch: ImageBuffer<Rgba<u8>, Vec<u8>>,
-    vertical_scratch: ImageBuffer<Rgba<u8>, Vec<u8>>,
-    diagonal_scratch_1: ImageBuffer<Rgba<u8>, Vec<u8>>, // Left to right
-    diagonal_scratch_2: ImageBuffer<Rgba<u8>, Vec<u8>>, // Right to left
+    sessions: Mutex<Sessions<Arc<Mutex<GameSession>>>>,
+    saved_sessions: Mutex<SavedSessions>,
+    restored: Mutex<Vec<RestoredSession>>, // Saved before the restart, waiting for the players
 
-    new_game_canvas: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    stats: Mutex<Stats>,
 
-    wait_user: Mutex<Option<(UserId, CommandInteraction, String, Message)>>,
+    pins: Mutex<Pins>,
+    theme_choices: Mutex<ThemeChoices>,
+}
 
-    sessions: Mutex<Vec<Arc<Mutex<GameSession>>>>,
+// Proposal which the opponent has to accept
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Offer {
+    Draw,
+    Takeback, // Of the last move of the player who asks, with the reply to it
 }
 
-#[derive(Clone, Copy, PartialEq)]
-enum GameCell {
-    None,
-    First,
-    Second,
+// Game and the position which the panel has been shown for. It's a part of the
+// ids of the panel buttons, so the presses on the old panels are told apart.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    game: u64,    // Id of the common message of the game
+    version: u64, // Of the position, see `GameSession::version`
 }
 
-impl Default for GameCell {
-    fn default() -> Self {
-        GameCell::None
+impl Stamp {
+    fn of(session: &GameSession) -> Self {
+        Self {
+            game: session.player.3.id.get(),
+            version: session.version,
+        }
+    }
+
+    fn id(self, action: &str) -> String {
+        format!("{}:{}:{}", action, self.game, self.version)
+    }
+
+    // Action of the button and the stamp of its panel
+    fn parse(custom_id: &str) -> Option<(&str, Self)> {
+        let mut parts = custom_id.rsplitn(3, ':');
+
+        let version = parts.next()?.parse().ok()?;
+        let game = parts.next()?.parse().ok()?;
+
+        Some((parts.next()?, Self { game, version }))
     }
 }
 
+#[derive(Clone, Copy)]
+struct HistoryMove {
+    player: Player,
+    index: usize, // Index for `Variant::play`
+    time: u64,    // Milliseconds since the start of the game
+}
+
+struct WaitingPlayer {
+    player: (UserId, CommandInteraction, String, Message),
+    mode: Mode, // Only the players who want the same rules and clock are paired
+    time_control: Option<TimeControl>,
+    rating: f64,
+    since: Instant,
+}
+
+struct Challenge {
+    player: (UserId, CommandInteraction, String, Message), // Message with the Accept and Decline buttons
+    opponent: UserId,
+    mode: Mode,
+}
+
 struct GameSession {
-    player: (UserId, CommandInteraction, String, Message), // Third element is a name of player
-    player2: (UserId, CommandInteraction, String, Option<Message>), // No message in a same channel
+    player: (UserId, PlayerInteraction, String, Message), // Third element is a name of player
+    player2: (UserId, Option<PlayerInteraction>, String, Option<Message>), // No interaction for the bot, no message in a same channel
+    bot: Option<Difficulty>,
+
+    state: Variant,
+    cursor_pos: CursorPos,
+    input: (Input, Input), // Of the first and the second player
+    theme: String,         // Of the player who has started the game
+    labels: bool,          // Letters and numbers around the board
+    history: Vec<HistoryMove>,
+    version: u64, // Grows with every move and takeback, a takeback doesn't bring back the old panels
+    started: Instant,
+    clock: Option<Clock>,           // No time limit without it
+    offer: Option<(Player, Offer)>, // Player who has made the offer
+    ended: bool, // Handlers which have waited for the lock see that the game is over
+}
+
+// Game of the previous run of the bot, it goes on when every player has
+// pressed the Resume button, which response becomes the new game panel
+struct RestoredSession {
+    saved: SavedSession,
+    message: Message,
+    message2: Option<Message>,
+    resumed: (Option<ComponentInteraction>, Option<ComponentInteraction>),
+}
+
+// Interaction which ephemeral response is the game panel of the player
+enum PlayerInteraction {
+    Command(CommandInteraction),
+    Component(ComponentInteraction), // Accepted challenge
+}
+
+impl PlayerInteraction {
+    async fn edit_response(
+        &self,
+        http: &Http,
+        builder: EditInteractionResponse,
+    ) -> serenity::Result<Message> {
+        match self {
+            PlayerInteraction::Command(interaction) => {
+                interaction.edit_response(http, builder).await
+            }
+            PlayerInteraction::Component(interaction) => {
+                interaction.edit_response(http, builder).await
+            }
+        }
+    }
+
+    // Accepted challenge has no options, so the default input is used
+    fn input(&self) -> Input {
+        match self {
+            PlayerInteraction::Command(interaction) => option_value(interaction, "input")
+                .and_then(|value| value.as_str())
+                .and_then(Input::from_name)
+                .unwrap_or_default(),
+            PlayerInteraction::Component(_) => Input::default(),
+        }
+    }
+
+    // Time control is chosen by the one who has chosen the rules
+    fn time_control(&self) -> Option<TimeControl> {
+        match self {
+            PlayerInteraction::Command(interaction) => time_control_from_options(interaction),
+            PlayerInteraction::Component(_) => None,
+        }
+    }
+
+    // Labels too, they are shown to everyone who watches the game
+    fn labels(&self) -> bool {
+        match self {
+            PlayerInteraction::Command(interaction) => option_value(interaction, "labels")
+                .and_then(|value| value.as_bool())
+                .unwrap_or(false),
+            PlayerInteraction::Component(_) => false,
+        }
+    }
+
+    fn guild_id(&self) -> Option<GuildId> {
+        match self {
+            PlayerInteraction::Command(interaction) => interaction.guild_id,
+            PlayerInteraction::Component(interaction) => interaction.guild_id,
+        }
+    }
+
+    // Interaction could have been answered before the error, then the error
+    // comes as a follow-up
+    async fn report_error(&self, http: &Http, err: Error) {
+        eprintln!("Failed to handle the interaction: {err:?}");
+
+        let embed = CreateEmbed::new().title(err.to_string());
+
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .ephemeral(true)
+                .embed(embed.clone()),
+        );
+        let followup = CreateInteractionResponseFollowup::new()
+            .ephemeral(true)
+            .embed(embed);
+
+        let result = match self {
+            PlayerInteraction::Command(interaction) => {
+                match interaction.create_response(http, response).await {
+                    Ok(()) => Ok(()),
+                    Err(_) => interaction.create_followup(http, followup).await.map(drop),
+                }
+            }
+            PlayerInteraction::Component(interaction) => {
+                match interaction.create_response(http, response).await {
+                    Ok(()) => Ok(()),
+                    Err(_) => interaction.create_followup(http, followup).await.map(drop),
+                }
+            }
+        };
 
-    stage: usize,
-    cursor_pos: usize,
+        if let Err(err) = result {
+            eprintln!("Failed to report the error: {err:?}");
+        }
+    }
 
-    map: [GameCell; 9],
-    canvas: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    async fn delete_response(&self, http: &Http) -> serenity::Result<()> {
+        match self {
+            PlayerInteraction::Command(interaction) => interaction.delete_response(http).await,
+            PlayerInteraction::Component(interaction) => interaction.delete_response(http).await,
+        }
+    }
 }
 
 impl Game {
     pub fn new() -> Self {
-        let x_image = image::open("./resources/x.png").expect("x.png").into_rgb8();
-        let o_image = image::open("./resources/o.png").expect("o.png").into_rgb8();
-
-        let horizontal_scratch = image::open("./resources/1.png")
-            .expect("1.png")
-            .into_rgba8();
-        let vertical_scratch = image::open("./resources/2.png")
-            .expect("2.png")
-            .into_rgba8();
-        let diagonal_scratch_1 = image::open("./resources/3.png")
-            .expect("3.png")
-            .into_rgba8();
-        let diagonal_scratch_2 = image::open("./resources/4.png")
-            .expect("4.png")
-            .into_rgba8();
-
-        let new_game_canvas = draw_new_game_canvas();
-
         Self {
-            x_image,
-            o_image,
+            render: RenderPool::new(Renderer::open()),
 
-            horizontal_scratch,
-            vertical_scratch,
-            diagonal_scratch_1,
-            diagonal_scratch_2,
+            stats: Mutex::new(Stats::open(STATS_PATH)),
 
-            new_game_canvas,
+            pins: Mutex::new(Pins::open(PINS_PATH)),
+            saved_sessions: Mutex::new(SavedSessions::open(SESSIONS_PATH)),
+            theme_choices: Mutex::new(ThemeChoices::open(THEME_CHOICES_PATH)),
+            queues: Mutex::new(Queues::open(QUEUE_POLICIES_PATH)),
 
             ..Default::default()
         }
     }
 
     pub fn register_play() -> CreateCommand {
-        CreateCommand::new("play").description("Start the game")
+        CreateCommand::new("play")
+            .description("Start the game")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "mode", "Variant of the game")
+                    .add_string_choice("Classic", "classic")
+                    .add_string_choice("Ultimate", "ultimate"),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "size", "Size of the board")
+                    .min_int_value(MIN_SIZE as u64)
+                    .max_int_value(MAX_SIZE as u64),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "win",
+                    "Count of pieces in a row needed for a win",
+                )
+                .min_int_value(MIN_SIZE as u64)
+                .max_int_value(MAX_SIZE as u64),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::User,
+                "opponent",
+                "Challenge the player or pick the bot to play against it",
+            ))
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "difficulty",
+                    "Skill of the bot, Perfect can't be beaten only on the 3×3 board",
+                )
+                .add_string_choice("Easy", "easy")
+                .add_string_choice("Medium", "medium")
+                .add_string_choice("Perfect", "perfect"),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "timeout",
+                    "Seconds to wait for the answer to the challenge",
+                )
+                .min_int_value(MIN_CHALLENGE_TIMEOUT)
+                .max_int_value(MAX_CHALLENGE_TIMEOUT),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "queue",
+                    "Where to wait for an opponent, the server setting by default",
+                )
+                .add_string_choice("Server", Policy::Fifo.name())
+                .add_string_choice("This channel", Policy::SameChannel.name())
+                .add_string_choice("All servers", Policy::CrossServer.name()),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "input",
+                    "Move with the arrows or press the cells, the cells work only on 3x3 boards",
+                )
+                .add_string_choice("Cursor", "cursor")
+                .add_string_choice("Grid", "grid"),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "time",
+                    "Time of every player and the increment per move, no limit by default",
+                )
+                .add_string_choice("1 min", "1+0")
+                .add_string_choice("3 min", "3+0")
+                .add_string_choice("5 min", "5+0")
+                .add_string_choice("10 min", "10+0")
+                .add_string_choice("1 min + 2 sec per move", "1+2")
+                .add_string_choice("3 min + 2 sec per move", "3+2")
+                .add_string_choice("5 min + 5 sec per move", "5+5"),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "labels",
+                "Show the letters of the columns and the numbers of the rows around the board",
+            ))
     }
 
     pub fn register_stop() -> CreateCommand {
-        CreateCommand::new("stop").description("Unimplemented")
+        CreateCommand::new("stop").description("Leave the queue or resign the current game")
+    }
+
+    pub fn register_stats() -> CreateCommand {
+        CreateCommand::new("stats")
+            .description("Show the results of the player")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::User,
+                "user",
+                "You by default",
+            ))
+    }
+
+    pub fn register_export() -> CreateCommand {
+        CreateCommand::new("export")
+            .description("Download the finished game in the text notation")
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "game",
+                    "Number of the game, your last game by default",
+                )
+                .min_int_value(1),
+            )
+    }
+
+    pub fn register_replay() -> CreateCommand {
+        CreateCommand::new("replay")
+            .description("Show the animated replay of the finished game")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "game", "Number of the game")
+                    .required(true)
+                    .min_int_value(1),
+            )
+    }
+
+    pub fn register_queue() -> CreateCommand {
+        CreateCommand::new("queue")
+            .description("Choose where the players of the server wait for an opponent")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "policy", "Matchmaking policy")
+                    .required(true)
+                    .add_string_choice("First come, first served", Policy::Fifo.name())
+                    .add_string_choice("Same channel only", Policy::SameChannel.name())
+                    .add_string_choice("Cross-server", Policy::CrossServer.name()),
+            )
+    }
+
+    // Choices are the themes found at the start, Discord shows 25 at most
+    pub fn register_theme(&self) -> CreateCommand {
+        let name = self.render.themes().iter().take(25).fold(
+            CreateCommandOption::new(CommandOptionType::String, "name", "Theme of the boards")
+                .required(true),
+            |option, (id, theme)| option.add_string_choice(&theme.name, id),
+        );
+
+        CreateCommand::new("theme")
+            .description("Choose how the boards of your games look")
+            .add_option(name)
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "server",
+                "Make it the theme of the server for the players without their own (admins only)",
+            ))
     }
 
     pub async fn command(&self, ctx: Context, interaction: CommandInteraction) {
-        if interaction.data.name == "stop" {
+        // Interaction can be moved into the game, so the copy reports the error
+        let reply_to = PlayerInteraction::Command(interaction.clone());
+
+        let result = match interaction.data.name.as_str() {
+            "stop" => self.stop(&ctx.http, &interaction).await,
+            "stats" => self.show_stats(&ctx.http, &interaction).await,
+            "leaderboard" => self.leaderboards().command(&ctx.http, &interaction).await,
+            "export" => self.export(&ctx.http, &interaction).await,
+            "replay" => self.replay(&ctx.http, &interaction).await,
+            "queue" => self.set_queue_policy(&ctx.http, &interaction).await,
+            "theme" => self.set_theme(&ctx.http, &interaction).await,
+            _ => self.play(&ctx, interaction).await,
+        };
+
+        if let Err(err) = result {
+            reply_to.report_error(&ctx.http, err).await;
+        }
+    }
+
+    async fn play(&self, ctx: &Context, interaction: CommandInteraction) -> Result<()> {
+        if self
+            .is_player_already_in_game(&ctx.http, &interaction)
+            .await?
+        {
+            return Ok(());
+        }
+
+        let Some(mode) = mode_from_options(&interaction) else {
             interaction
                 .create_response(
                     &ctx.http,
                     CreateInteractionResponse::Message(
                         CreateInteractionResponseMessage::new()
                             .ephemeral(true)
-                            .content("Unimplemented!"),
+                            .embed(CreateEmbed::new().title("Invalid rules").description(
+                            "Count of pieces in a row can't be bigger than the size of the board.",
+                        )),
                     ),
                 )
-                .await
-                .unwrap();
+                .await?;
 
-            return;
-        }
+            return Ok(());
+        };
 
-        if self
-            .is_player_already_in_game(&ctx.http, &interaction)
-            .await
-        {
-            return;
-        }
+        let (bot_id, bot_name) = {
+            let bot = ctx.cache.current_user();
+            (bot.id, bot.name.clone())
+        };
 
-        let (player, player2) = {
-            let val = { self.wait_user.lock().await.take() };
+        let bot = match option_value(&interaction, "opponent").and_then(|value| value.as_user_id())
+        {
+            Some(opponent) if opponent == bot_id => {
+                let difficulty = option_value(&interaction, "difficulty")
+                    .and_then(|value| value.as_str())
+                    .and_then(Difficulty::from_name)
+                    .unwrap_or(Difficulty::Medium);
 
-            let name = match &interaction.member {
-                Some(val) => val
-                    .nick
-                    .clone()
-                    .unwrap_or_else(|| interaction.user.name.clone()),
-                None => interaction.user.name.clone(),
-            };
+                Some(difficulty)
+            }
 
-            if let Some(val) = val {
+            Some(opponent) if !is_valid_opponent(&interaction, opponent) => {
                 interaction
                     .create_response(
                         &ctx.http,
                         CreateInteractionResponse::Message(
                             CreateInteractionResponseMessage::new()
                                 .ephemeral(true)
-                                .embed(CreateEmbed::new().title("Please, wait")),
+                                .embed(CreateEmbed::new().title("Invalid opponent").description(
+                                    "You can't play against yourself or against other bots.",
+                                )),
                         ),
                     )
-                    .await
-                    .unwrap();
+                    .await?;
+
+                return Ok(());
+            }
 
+            Some(opponent) => return self.challenge(ctx, interaction, opponent, mode).await,
+
+            None => None,
+        };
+
+        let policy = {
+            let queues = self.queues.lock().await;
+
+            option_value(&interaction, "queue")
+                .and_then(|value| value.as_str())
+                .and_then(Policy::from_name)
+                .unwrap_or_else(|| queues.policy(interaction.guild_id))
+        };
+
+        let key = QueueKey::new(policy, interaction.guild_id, interaction.channel_id);
+        let rating = self.stats.lock().await.rating(interaction.user.id);
+        let time_control = time_control_from_options(&interaction);
+
+        // Answered before the queue is touched, so a failed answer leaves the
+        // waiting player in the queue
+        interaction
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .ephemeral(true)
+                        .embed(CreateEmbed::new().title("Please, wait")),
+                ),
+            )
+            .await?;
+
+        let (player, player2, mode) = {
+            let name = player_name(&interaction);
+
+            if let Some(difficulty) = bot {
+                let bot_name = format!("{} ({})", bot_name, difficulty.name());
+
+                let message = interaction
+                    .channel_id
+                    .send_message(
+                        &ctx.http,
+                        CreateMessage::new().embed(CreateEmbed::new().title(format!(
+                            "The game between {} and {} in progress!",
+                            name, bot_name,
+                        ))),
+                    )
+                    .await?;
+
+                (
+                    (
+                        interaction.user.id,
+                        PlayerInteraction::Command(interaction),
+                        name,
+                        message,
+                    ),
+                    (bot_id, None, bot_name, None),
+                    mode,
+                )
+            } else if let Some(WaitingPlayer {
+                player: val, mode, ..
+            }) = self.queues.lock().await.take(key, |val| {
+                // Second `/play` of the same user could have come while this one waited
+                val.player.0 != interaction.user.id
+                    && val.mode == mode
+                    && val.time_control == time_control
+                    && rating::in_window(val.rating, rating, val.since.elapsed())
+            }) {
                 // Channel ids are unique
-                if interaction.channel_id != val.1.channel_id {
+                let message = if interaction.channel_id != val.1.channel_id {
                     let message = interaction
                         .channel_id
                         .send_message(
@@ -176,13 +591,31 @@ impl Game {
                                 val.2, name,
                             ))),
                         )
-                        .await
-                        .unwrap();
+                        .await;
 
-                    (val, (interaction.user.id, interaction, name, Some(message)))
+                    // The waiting player is out of the queue already, so the game
+                    // goes on with the message in his/her/their channel only
+                    match message {
+                        Ok(message) => Some(message),
+                        Err(err) => {
+                            eprintln!("Failed to send the game message: {err:?}");
+                            None
+                        }
+                    }
                 } else {
-                    (val, (interaction.user.id, interaction, name, None))
-                }
+                    None
+                };
+
+                (
+                    (val.0, PlayerInteraction::Command(val.1), val.2, val.3),
+                    (
+                        interaction.user.id,
+                        Some(PlayerInteraction::Command(interaction)),
+                        name,
+                        message,
+                    ),
+                    mode,
+                )
             } else {
                 let icon_url = interaction
                     .user
@@ -196,729 +629,2219 @@ impl Game {
                         CreateMessage::new().embed(
                             CreateEmbed::new()
                                 .author(CreateEmbedAuthor::new(name.clone()).icon_url(icon_url))
-                                .title(format!("{} wants to play tic-tac-toe game!", name))
-                                .description(
-                                    "You can join to him/her/them by using the `/play` command.",
-                                ),
+                                .title(format!(
+                                    "{} wants to play tic-tac-toe game{}{}!",
+                                    name,
+                                    describe_mode(mode),
+                                    describe_time_control(&interaction),
+                                ))
+                                .description(describe_queue(key)),
                         ),
                     )
-                    .await
-                    .unwrap();
+                    .await?;
 
                 interaction
-                    .create_response(
+                    .edit_response(
                         &ctx.http,
-                        CreateInteractionResponse::Message(
-                            CreateInteractionResponseMessage::new()
-                                .ephemeral(true)
-                                .embed(
-                                    CreateEmbed::new().title("Please, wait for second player..."),
-                                ),
-                        ),
+                        EditInteractionResponse::new()
+                            .embed(CreateEmbed::new().title("Please, wait for second player...")),
                     )
-                    .await
-                    .unwrap();
-
-                *self.wait_user.lock().await =
-                    Some((interaction.user.id, interaction, name, message));
-                return;
+                    .await?;
+
+                self.queues.lock().await.push(
+                    key,
+                    WaitingPlayer {
+                        player: (interaction.user.id, interaction, name, message),
+                        mode,
+                        time_control,
+                        rating,
+                        since: Instant::now(),
+                    },
+                );
+                return Ok(());
             }
         };
 
-        let new_game = Arc::new(Mutex::new(GameSession {
-            player,
-            player2,
+        self.start_session(&ctx.http, player, player2, bot, mode)
+            .await
+    }
 
-            stage: 0,
-            cursor_pos: 4,
+    pub fn start_background_tasks(self: &Arc<Self>, http: Arc<Http>) {
+        // Ready event comes again after every reconnect
+        if self.background_tasks.swap(true, Ordering::SeqCst) {
+            return;
+        }
 
-            map: Default::default(),
-            canvas: self.new_game_canvas.clone(),
-        }));
+        let game = Arc::clone(self);
+        let game_http = Arc::clone(&http);
 
-        {
-            self.sessions.lock().await.push(Arc::clone(&new_game));
-        }
+        tokio::spawn(async move {
+            game.restore_sessions(&game_http).await;
+        });
 
-        self.process_session(&ctx.http, &mut *new_game.lock().await)
-            .await;
-    }
+        let game = Arc::clone(self);
+        let game_http = Arc::clone(&http);
 
-    async fn is_player_already_in_game(
-        &self,
-        http: &Http,
-        interaction: &CommandInteraction,
-    ) -> bool {
-        let message = CreateInteractionResponse::Message(
-            CreateInteractionResponseMessage::new()
-                .ephemeral(true)
-                .embed(
-                    CreateEmbed::new()
-                        .title("Start a new game")
-                        .description("You have already in the game. For starting a new game you should use the `/stop` command.")
-                )
-        );
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(MATCHMAKING_INTERVAL).await;
 
-        {
-            if let Some(val) = self.wait_user.lock().await.as_ref() {
-                if val.0 == interaction.user.id {
-                    interaction.create_response(http, message).await.unwrap();
+                game.match_waiting_players(&game_http).await;
+            }
+        });
 
-                    return true;
-                }
+        let game = Arc::clone(self);
+        let game_http = Arc::clone(&http);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(CLOCK_CHECK_INTERVAL).await;
+
+                game.check_clocks(&game_http).await;
             }
-        }
+        });
 
-        let sessions = self.sessions.lock().await;
+        let game = Arc::clone(self);
 
-        for session in &*sessions {
-            let session = session.lock().await;
+        tokio::spawn(async move {
+            let mut periods_refreshed = Instant::now();
 
-            if session.player.0 == interaction.user.id || session.player2.0 == interaction.user.id {
-                interaction.create_response(http, message).await.unwrap();
+            loop {
+                tokio::time::sleep(LEADERBOARD_REFRESH_INTERVAL).await;
+
+                if periods_refreshed.elapsed() >= PERIOD_REFRESH_INTERVAL {
+                    game.pins.lock().await.mark_periods_outdated();
+                    periods_refreshed = Instant::now();
+                }
 
-                return true;
+                game.leaderboards().refresh(&http).await;
             }
-        }
+        });
 
-        false
-    }
+        let game = Arc::clone(self);
 
-    async fn process_session(&self, http: &Http, session: &mut GameSession) {
-        match session.stage {
-            0 => {
-                show_game_message(
-                    http,
-                    &session.player.1,
-                    session.cursor_pos,
-                    &session.map,
-                    &session.canvas,
-                )
-                .await;
+        // Latencies of the drawing for sizing of the pool of the workers
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(RENDER_METRICS_INTERVAL).await;
 
-                show_wait_and_common_message(
-                    http,
-                    &session.player2.1,
-                    &session.canvas,
-                    &session.player.2,
-                    &session.player2.2,
-                    &mut session.player.3,
-                    session.player2.3.as_mut(),
-                )
-                .await;
-            }
-            1 => {
-                show_game_message(
-                    http,
-                    &session.player2.1,
-                    session.cursor_pos,
-                    &session.map,
-                    &session.canvas,
-                )
-                .await;
+                let metrics = game.render.take_metrics();
 
-                show_wait_and_common_message(
-                    http,
-                    &session.player.1,
-                    &session.canvas,
-                    &session.player.2,
-                    &session.player2.2,
-                    &mut session.player.3,
-                    session.player2.3.as_mut(),
-                )
-                .await;
+                if !metrics.is_empty() {
+                    eprintln!("Rendering: {metrics}");
+                }
             }
-            _ => unreachable!(),
-        }
+        });
     }
 
-    pub async fn component(&self, ctx: Context, component: ComponentInteraction) {
-        // We are calling this because we are editing the component
-        // interaction or answering to the original interaction in the progress_game()
-        component
-            .create_response(&ctx.http, CreateInteractionResponse::Acknowledge)
-            .await
-            .unwrap();
+    // Games which were running when the bot stopped get the Resume button
+    async fn restore_sessions(&self, http: &Http) {
+        let saved_sessions = self.saved_sessions.lock().await.sessions().to_vec();
 
-        let original_session = self.get_current_game(&component).await.unwrap();
-        let mut session = original_session.lock().await;
+        for saved in saved_sessions {
+            let (_, _, channel_id, message_id) = saved.player;
 
-        match component.data.custom_id.as_str() {
-            "left" => {
-                if ![0, 3, 6].contains(&session.cursor_pos) {
-                    session.cursor_pos -= 1;
-                }
+            let message = match channel_id.message(http, message_id).await {
+                Ok(message) => message,
+                Err(err) => {
+                    eprintln!("Failed to restore the game: {err:?}");
 
-                update_game_message(&ctx.http, &component, &session).await;
-            }
+                    // Message has been deleted, the game can't go on. Otherwise
+                    // Discord may answer after the next start
+                    if is_unknown_message(&err) {
+                        self.saved_sessions.lock().await.remove(saved.player.0);
+                    }
 
-            "down" => {
-                if session.cursor_pos <= 5 {
-                    session.cursor_pos += 3
+                    continue;
                 }
+            };
+
+            let message2 = match saved.player2.2 {
+                Some((channel_id, message_id)) => channel_id.message(http, message_id).await.ok(),
+                None => None,
+            };
+
+            // Corrupted game can't go on, no one has lost it
+            let Some((state, _)) = restore_state(&saved) else {
+                eprintln!(
+                    "Failed to restore the game of {}: the saved moves can't be replayed",
+                    saved.player.1,
+                );
+                self.saved_sessions.lock().await.remove(saved.player.0);
+                continue;
+            };
 
-                update_game_message(&ctx.http, &component, &session).await;
+            let mut scene = Scene::new(&state, &saved.theme).labels(saved.labels);
+
+            if let Some(index) = saved.moves.last() {
+                scene = scene.last_move(*index);
             }
 
-            "up" => {
-                if session.cursor_pos >= 3 {
-                    session.cursor_pos -= 3
+            let attachment = match self.board_attachment(scene).await {
+                Ok(attachment) => attachment,
+                Err(err) => {
+                    eprintln!("Failed to show the Resume button: {err:?}");
+                    continue;
                 }
+            };
 
-                update_game_message(&ctx.http, &component, &session).await;
-            }
+            let edited_message = EditMessage::new()
+                .embed(
+                    CreateEmbed::new()
+                        .title(format!(
+                            "Game between {} and {} has been interrupted!",
+                            saved.player.1, saved.player2.1,
+                        ))
+                        .description(format!(
+                            "The bot has restarted. Press Resume to continue the game, {} is on turn.",
+                            match state.turn() {
+                                Player::First => &saved.player.1,
+                                Player::Second => &saved.player2.1,
+                            },
+                        ))
+                        .attachment("canvas.png"),
+                )
+                .components(vec![generate_resume_action_row()])
+                .attachment(attachment);
+
+            let mut restored = RestoredSession {
+                saved,
+                message,
+                message2,
+                resumed: (None, None),
+            };
 
-            "right" => {
-                if ![2, 5, 8].contains(&session.cursor_pos) {
-                    session.cursor_pos += 1
+            let result = async {
+                if let Some(message) = &mut restored.message2 {
+                    message.edit(http, edited_message.clone()).await?;
                 }
 
-                update_game_message(&ctx.http, &component, &session).await;
+                restored.message.edit(http, edited_message).await
             }
+            .await;
 
-            "send" => {
-                'condition: {
-                    if component.user.id == session.player.0 {
-                        if session.map[session.cursor_pos] != GameCell::None {
-                            // Unreachable in default situation
-                            break 'condition;
-                        }
-
-                        let cursor_pos = session.cursor_pos;
-                        session.map[cursor_pos] = GameCell::First;
-                        self.draw_x(&mut session.canvas, cursor_pos);
-                    } else {
-                        if session.map[session.cursor_pos] != GameCell::None {
-                            break 'condition;
-                        }
+            // Game without the button still can be cancelled with /stop
+            if let Err(err) = result {
+                eprintln!("Failed to show the Resume button: {err:?}");
+            }
 
-                        let cursor_pos = session.cursor_pos;
-                        session.map[cursor_pos] = GameCell::Second;
-                        self.draw_o(&mut session.canvas, cursor_pos);
-                    }
-                };
+            self.restored.lock().await.push(restored);
+        }
+    }
 
-                let map = &session.map;
-
-                // Checking for win
-                // 0 1 2
-                // 3 4 5
-                // 6 7 8
-                let (win_player, id) = if map[0] != GameCell::None
-                    && (map[0] == map[1])
-                    && (map[1] == map[2])
-                {
-                    (map[0], 0)
-                } else if map[3] != GameCell::None && (map[3] == map[4]) && (map[4] == map[5]) {
-                    (map[3], 1)
-                } else if map[6] != GameCell::None && (map[6] == map[7]) && (map[7] == map[8]) {
-                    (map[6], 2)
-                } else if map[0] != GameCell::None && (map[0] == map[3]) && (map[3] == map[6]) {
-                    (map[0], 3)
-                } else if map[1] != GameCell::None && (map[1] == map[4]) && (map[4] == map[7]) {
-                    (map[1], 4)
-                } else if map[2] != GameCell::None && (map[2] == map[5]) && (map[5] == map[8]) {
-                    (map[2], 5)
-                } else if map[0] != GameCell::None && (map[0] == map[4]) && (map[4] == map[8]) {
-                    (map[0], 6)
-                } else if map[2] != GameCell::None && (map[2] == map[4]) && (map[4] == map[6]) {
-                    (map[2], 7)
-                } else {
-                    let mut was_none = false;
-                    for cell in map {
-                        if *cell == GameCell::None {
-                            was_none = true;
-                            break;
-                        }
-                    }
+    async fn resume(&self, ctx: &Context, component: ComponentInteraction) -> Result<()> {
+        let user_id = component.user.id;
+        let mut restored = self.restored.lock().await;
 
-                    if !was_none {
-                        let message = EditMessage::new()
-                            .add_embed(
-                                CreateEmbed::new()
-                                    .title(format!(
-                                        "The game between {} and {} has finished!",
-                                        session.player.2, session.player2.2,
-                                    ))
-                                    .description("No one wins!")
-                                    .attachment("canvas.png"),
-                            )
-                            .attachment(generate_attachment_rgb8(&session.canvas, "canvas.png"));
-
-                        self.end_game_with_message(
-                            &ctx.http,
-                            &mut session,
-                            &original_session,
-                            message,
-                        )
-                        .await;
-                        return;
-                    }
+        let pos = restored.iter().position(|val| {
+            val.message.id == component.message.id
+                || matches!(&val.message2, Some(message) if message.id == component.message.id)
+        });
 
-                    session.stage = (session.stage + 1) % 2;
-                    session.cursor_pos = 4;
+        let Some(pos) = pos else {
+            return Err(Error::GameFinished);
+        };
 
-                    self.process_session(&ctx.http, &mut session).await;
-                    return;
-                };
+        let val = &mut restored[pos];
 
-                let attachment = self.generate_end_attachment(&mut session, id).await;
+        let resumed = if val.saved.player.0 == user_id {
+            &mut val.resumed.0
+        } else if val.saved.player2.0 == user_id && val.saved.bot.is_none() {
+            &mut val.resumed.1
+        } else {
+            return Err(Error::NotInGame);
+        };
 
-                match win_player {
-                    GameCell::First => {
-                        let message = EditMessage::new()
-                            .add_embed(
-                                CreateEmbed::new()
-                                    .title(format!(
-                                        "The game between {} and {} has finished!",
-                                        session.player.2, session.player.2,
-                                    ))
-                                    .description(format!("💥 {} has won! 💥", session.player.2))
-                                    .attachment("canvas.png"),
-                            )
-                            .attachment(attachment);
-
-                        self.end_game_with_message(
-                            &ctx.http,
-                            &mut session,
-                            &original_session,
-                            message,
-                        )
-                        .await;
-                    }
-                    GameCell::Second => {
-                        let message = EditMessage::new()
-                            .add_embed(
-                                CreateEmbed::new()
-                                    .title(format!(
-                                        "The game between {} and {} has finished!",
-                                        session.player.2, session.player2.2,
-                                    ))
-                                    .description(format!("💥 {} has won! 💥", session.player.2))
-                                    .attachment("canvas.png"),
-                            )
-                            .attachment(attachment);
-
-                        self.end_game_with_message(
-                            &ctx.http,
-                            &mut session,
-                            &original_session,
-                            message,
-                        )
-                        .await;
-                    }
-                    GameCell::None => unreachable!(),
-                }
-            }
-            _ => unreachable!(),
+        if resumed.is_some() {
+            return reply_component(&ctx.http, &component, "You have resumed this game already.")
+                .await;
         }
-    }
 
-    async fn get_current_game(
-        &self,
-        message_component: &ComponentInteraction,
-    ) -> Option<Arc<Mutex<GameSession>>> {
-        let sessions = self.sessions.lock().await;
-
-        let mut has_game = None;
-        for session in sessions.iter() {
-            let session_lock = session.lock().await;
-            if session_lock.player.0 == message_component.user.id
-                || session_lock.player2.0 == message_component.user.id
-            {
-                has_game = Some(Arc::clone(session));
+        // Response to the button becomes the game panel of the player
+        reply_component(&ctx.http, &component, "Please, wait for the opponent...").await?;
+        *resumed = Some(component);
+
+        let RestoredSession {
+            saved,
+            message,
+            message2,
+            resumed,
+        } = restored.swap_remove(pos);
+
+        let (interaction, interaction2) = match resumed {
+            (Some(interaction), Some(interaction2)) => (interaction, Some(interaction2)),
+            (Some(interaction), None) if saved.bot.is_some() => (interaction, None),
+
+            // The opponent hasn't resumed yet
+            resumed => {
+                restored.push(RestoredSession {
+                    saved,
+                    message,
+                    message2,
+                    resumed,
+                });
+
+                return Ok(());
             }
-        }
+        };
 
-        has_game
-    }
+        drop(restored);
 
-    fn draw_x(&self, image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>, cell_index: usize) {
-        for y in 0..80 {
-            for x in 0..80 {
-                image.draw_pixel(
-                    CELLS[cell_index].0 as u32 + 10 + x,
-                    CELLS[cell_index].1 as u32 + 10 + y,
-                    *self.x_image.get_pixel(x, y),
-                );
-            }
-        }
-    }
+        // Resume button goes away with the first panels
+        let Some((state, history)) = restore_state(&saved) else {
+            self.saved_sessions.lock().await.remove(saved.player.0);
+            return Err(Error::InvalidMoves);
+        };
 
-    fn draw_o(&self, image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>, cell_index: usize) {
-        for y in 0..80 {
-            for x in 0..80 {
-                image.draw_pixel(
-                    CELLS[cell_index].0 as u32 + 10 + x,
-                    CELLS[cell_index].1 as u32 + 10 + y,
-                    *self.o_image.get_pixel(x, y),
-                );
-            }
+        let clock = saved.clock.map(|(control, remaining, remaining2)| {
+            Clock::restore(control, (remaining, remaining2), state.turn())
+        });
+
+        let session = self
+            .add_session(GameSession {
+                player: (
+                    saved.player.0,
+                    PlayerInteraction::Component(interaction),
+                    saved.player.1,
+                    message,
+                ),
+                player2: (
+                    saved.player2.0,
+                    interaction2.map(PlayerInteraction::Component),
+                    saved.player2.1,
+                    message2,
+                ),
+                bot: saved.bot,
+
+                cursor_pos: initial_cursor(&state), // Where it was after the last move
+                state,
+                input: saved.input,
+                theme: saved.theme,
+                labels: saved.labels,
+                history,
+                version: saved.version,
+                started: Instant::now()
+                    .checked_sub(saved.elapsed)
+                    .unwrap_or_else(Instant::now),
+                clock,
+                offer: None,
+                ended: false,
+            })
+            .await;
+
+        let mut game = session.lock().await;
+
+        self.process_session(&ctx.http, &mut game).await
+    }
+
+    // New panel for the player whose old one has expired or has been dismissed
+    async fn show_controls(&self, ctx: &Context, component: ComponentInteraction) -> Result<()> {
+        let session = self
+            .sessions
+            .lock()
+            .await
+            .by_message(component.message.id)
+            .cloned()
+            .ok_or(Error::GameFinished)?;
+
+        let mut session = session.lock().await;
+        let session = &mut *session;
+
+        if session.ended {
+            return Err(Error::GameFinished);
+        }
+
+        let player = player_of(session, component.user.id).ok_or(Error::NotInGame)?;
+
+        reply_component(&ctx.http, &component, "Please, wait").await?;
+
+        set_interaction(session, player, component);
+
+        show_panels(&ctx.http, &self.render, session).await
+    }
+
+    // Game which hasn't been resumed by everyone is just dropped, no one has lost it
+    async fn cancel_restored(&self, http: &Http, user_id: UserId) -> Result<bool> {
+        let restored = {
+            let mut restored = self.restored.lock().await;
+
+            let Some(pos) = restored
+                .iter()
+                .position(|val| val.saved.player.0 == user_id || val.saved.player2.0 == user_id)
+            else {
+                return Ok(false);
+            };
+
+            restored.swap_remove(pos)
+        };
+
+        self.saved_sessions
+            .lock()
+            .await
+            .remove(restored.saved.player.0);
+
+        let RestoredSession {
+            saved,
+            mut message,
+            mut message2,
+            resumed: (interaction, interaction2),
+        } = restored;
+
+        for interaction in [interaction, interaction2].into_iter().flatten() {
+            log_panel_error(interaction.delete_response(http).await);
+        }
+
+        let edited_message = EditMessage::new()
+            .embed(CreateEmbed::new().title(format!(
+                "The game between {} and {} has been cancelled.",
+                saved.player.1, saved.player2.1,
+            )))
+            .components(vec![]);
+
+        if let Some(message) = &mut message2 {
+            message.edit(http, edited_message.clone()).await?;
+        }
+
+        message.edit(http, edited_message).await?;
+
+        Ok(true)
+    }
+
+    async fn save_session(&self, session: &GameSession) {
+        let saved = SavedSession {
+            player: (
+                session.player.0,
+                session.player.2.clone(),
+                session.player.3.channel_id,
+                session.player.3.id,
+            ),
+            player2: (
+                session.player2.0,
+                session.player2.2.clone(),
+                session
+                    .player2
+                    .3
+                    .as_ref()
+                    .map(|message| (message.channel_id, message.id)),
+            ),
+            bot: session.bot,
+
+            mode: session.state.mode(),
+            moves: session.history.iter().map(|val| val.index).collect(),
+            times: session.history.iter().map(|val| val.time).collect(),
+            elapsed: session.started.elapsed(),
+            input: session.input,
+            theme: session.theme.clone(),
+            labels: session.labels,
+            version: session.version,
+            clock: session.clock.as_ref().map(|clock| {
+                (
+                    clock.control(),
+                    clock.remaining(Player::First),
+                    clock.remaining(Player::Second),
+                )
+            }),
+        };
+
+        self.saved_sessions.lock().await.put(saved);
+    }
+
+    // Game is indexed by its players and messages, which stay the same until
+    // it ends
+    async fn add_session(&self, session: GameSession) -> Arc<Mutex<GameSession>> {
+        let game = session.player.3.id;
+
+        let mut users = vec![session.player.0];
+        if session.bot.is_none() {
+            users.push(session.player2.0);
+        }
+
+        let messages = iter::once(&session.player.3)
+            .chain(&session.player2.3)
+            .map(|message| message.id)
+            .collect();
+
+        let session = Arc::new(Mutex::new(session));
+
+        self.sessions
+            .lock()
+            .await
+            .insert(game, users, messages, Arc::clone(&session));
+
+        session
+    }
+
+    async fn check_clocks(&self, http: &Http) {
+        let sessions: Vec<_> = self.sessions.lock().await.iter().cloned().collect();
+
+        for original_session in sessions {
+            // Busy session is checked by the next tick
+            let Ok(mut session) = original_session.try_lock() else {
+                continue;
+            };
+
+            if let Err(err) = self.flag_fall(http, &mut session).await {
+                eprintln!("Failed to end the game on time: {err:?}");
+            }
+        }
+    }
+
+    // Ends the game if the player on turn has run out of time
+    async fn flag_fall(&self, http: &Http, session: &mut GameSession) -> Result<bool> {
+        if session.ended || !matches!(&session.clock, Some(clock) if clock.is_flagged()) {
+            return Ok(false);
+        }
+
+        let winner = session.state.turn().opponent();
+
+        self.end_off_board(http, session, Some(winner), Ending::Time)
+            .await?;
+
+        Ok(true)
+    }
+
+    // Rating windows grow while the players wait, so the ones who didn't fit
+    // each other at first can be paired later
+    async fn match_waiting_players(&self, http: &Http) {
+        loop {
+            let pair = self.queues.lock().await.take_pair(|val, val2| {
+                val.player.0 != val2.player.0
+                    && val.mode == val2.mode
+                    && val.time_control == val2.time_control
+                    && rating::in_window(val.rating, val2.rating, val.since.elapsed())
+            });
+
+            let Some((val, val2)) = pair else {
+                return;
+            };
+
+            let WaitingPlayer {
+                player: (id, interaction, name, message),
+                ..
+            } = val2;
+
+            // One message for the game is enough in a same channel
+            let message = if interaction.channel_id == val.player.1.channel_id {
+                // Stray message doesn't stop the game
+                if let Err(err) = message.delete(http).await {
+                    eprintln!("Failed to delete the waiting message: {err:?}");
+                }
+                None
+            } else {
+                Some(message)
+            };
+
+            let WaitingPlayer {
+                player: val, mode, ..
+            } = val;
+
+            let result = self
+                .start_session(
+                    http,
+                    (val.0, PlayerInteraction::Command(val.1), val.2, val.3),
+                    (
+                        id,
+                        Some(PlayerInteraction::Command(interaction)),
+                        name,
+                        message,
+                    ),
+                    None,
+                    mode,
+                )
+                .await;
+
+            if let Err(err) = result {
+                eprintln!("Failed to start the game: {err:?}");
+            }
+        }
+    }
+
+    async fn start_session(
+        &self,
+        http: &Http,
+        player: (UserId, PlayerInteraction, String, Message),
+        player2: (UserId, Option<PlayerInteraction>, String, Option<Message>),
+        bot: Option<Difficulty>,
+        mode: Mode,
+    ) -> Result<()> {
+        let state = Variant::new(mode);
+        let input = (
+            player.1.input(),
+            player2
+                .1
+                .as_ref()
+                .map_or_else(Input::default, PlayerInteraction::input),
+        );
+
+        let clock = player.1.time_control().map(Clock::new);
+        let labels = player.1.labels();
+        let theme = self.theme_for(player.0, player.1.guild_id()).await;
+
+        let new_game = self
+            .add_session(GameSession {
+                player,
+                player2,
+                bot,
+
+                cursor_pos: initial_cursor(&state),
+                state,
+                input,
+                theme,
+                labels,
+                history: Vec::new(),
+                version: 0,
+                started: Instant::now(),
+                clock,
+                offer: None,
+                ended: false,
+            })
+            .await;
+
+        let mut session = new_game.lock().await;
+
+        self.process_session(http, &mut session).await
+    }
+
+    async fn challenge(
+        &self,
+        ctx: &Context,
+        interaction: CommandInteraction,
+        opponent: UserId,
+        mode: Mode,
+    ) -> Result<()> {
+        if self.is_in_game(opponent).await {
+            interaction
+                .create_response(
+                    &ctx.http,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .ephemeral(true)
+                            .embed(
+                                CreateEmbed::new()
+                                    .title("Invalid opponent")
+                                    .description("This player is already in the game."),
+                            ),
+                    ),
+                )
+                .await?;
+
+            return Ok(());
+        }
+
+        let name = player_name(&interaction);
+        let timeout = option_value(&interaction, "timeout")
+            .and_then(|value| value.as_i64())
+            .map_or(CHALLENGE_TIMEOUT, |value| value as u64);
+
+        interaction
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .ephemeral(true)
+                        .embed(CreateEmbed::new().title("Please, wait for the answer...")),
+                ),
+            )
+            .await?;
+
+        let icon_url = interaction
+            .user
+            .avatar_url()
+            .unwrap_or_else(|| interaction.user.default_avatar_url());
+
+        let message = interaction
+            .channel_id
+            .send_message(
+                &ctx.http,
+                CreateMessage::new()
+                    .content(opponent.mention().to_string())
+                    .embed(
+                        CreateEmbed::new()
+                            .author(CreateEmbedAuthor::new(name.clone()).icon_url(icon_url))
+                            .title(format!(
+                                "{} challenges you to tic-tac-toe game{}{}!",
+                                name,
+                                describe_mode(mode),
+                                describe_time_control(&interaction),
+                            ))
+                            .description(format!("The challenge expires in {} seconds.", timeout,)),
+                    )
+                    .components(vec![generate_challenge_action_row()]),
+            )
+            .await?;
+
+        let message_id = message.id;
+
+        self.challenges.lock().await.push(Challenge {
+            player: (interaction.user.id, interaction, name, message),
+            opponent,
+            mode,
+        });
+
+        let challenges = Arc::clone(&self.challenges);
+        let http = Arc::clone(&ctx.http);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(timeout)).await;
+
+            // Nothing to do if the challenge has been answered already
+            let Some(challenge) =
+                take_challenge(&challenges, |val| val.player.3.id == message_id).await
+            else {
+                return;
+            };
+
+            let result = close_challenge(
+                &http,
+                challenge,
+                "The challenge has expired, no one has answered it.",
+            )
+            .await;
+
+            if let Err(err) = result {
+                eprintln!("Failed to close the challenge: {err:?}");
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn answer_challenge(&self, ctx: &Context, component: ComponentInteraction) -> Result<()> {
+        let reply = |title: &'static str| {
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .ephemeral(true)
+                    .embed(CreateEmbed::new().title(title)),
+            )
+        };
+
+        let opponent = self
+            .challenges
+            .lock()
+            .await
+            .iter()
+            .find(|val| val.player.3.id == component.message.id)
+            .map(|val| val.opponent);
+
+        match opponent {
+            None => {
+                component
+                    .create_response(&ctx.http, reply("This challenge has expired."))
+                    .await?;
+
+                return Ok(());
+            }
+
+            Some(opponent) if opponent != component.user.id => {
+                component
+                    .create_response(&ctx.http, reply("This challenge isn't for you."))
+                    .await?;
+
+                return Ok(());
+            }
+
+            Some(_) => {}
+        }
+
+        let accepted = component.data.custom_id == "accept";
+
+        if accepted && self.is_in_game(component.user.id).await {
+            component
+                .create_response(
+                    &ctx.http,
+                    reply("You are already in the game. Use the `/stop` command first."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+
+        // Response to the button becomes the game panel of the opponent. It's
+        // sent before the challenge is taken, so a failed one doesn't lose it.
+        if accepted {
+            component
+                .create_response(&ctx.http, reply("Please, wait"))
+                .await?;
+        }
+
+        let Some(challenge) = take_challenge(&self.challenges, |val| {
+            val.player.3.id == component.message.id
+        })
+        .await
+        else {
+            let expired = CreateEmbed::new().title("This challenge has expired.");
+
+            if accepted {
+                component
+                    .edit_response(&ctx.http, EditInteractionResponse::new().embed(expired))
+                    .await?;
+            } else {
+                component
+                    .create_response(&ctx.http, reply("This challenge has expired."))
+                    .await?;
+            }
+
+            return Ok(());
+        };
+
+        let name = member_name(component.member.as_ref(), &component.user);
+
+        // Challenge is closed before the answer to the button, so a failed
+        // answer doesn't leave it open
+        if !accepted {
+            let text = format!("{} has declined the challenge.", name);
+            close_challenge(&ctx.http, challenge, &text).await?;
+
+            component
+                .create_response(&ctx.http, CreateInteractionResponse::Acknowledge)
+                .await?;
+
+            return Ok(());
+        }
+
+        let Challenge {
+            player: (id, interaction, player_name, message),
+            mode,
+            ..
+        } = challenge;
+
+        // Challenge message becomes the common one of the game
+        let player = (
+            id,
+            PlayerInteraction::Command(interaction),
+            player_name,
+            message,
+        );
+        let player2 = (
+            component.user.id,
+            Some(PlayerInteraction::Component(component)),
+            name,
+            None,
+        );
+
+        self.start_session(&ctx.http, player, player2, None, mode)
+            .await
+    }
+
+    // Waiting in the queue and waiting for the answer to the challenge count too
+    async fn is_in_game(&self, user_id: UserId) -> bool {
+        if self
+            .queues
+            .lock()
+            .await
+            .contains(|val| val.player.0 == user_id)
+        {
+            return true;
+        }
+
+        if self
+            .challenges
+            .lock()
+            .await
+            .iter()
+            .any(|val| val.player.0 == user_id)
+        {
+            return true;
+        }
+
+        if self
+            .restored
+            .lock()
+            .await
+            .iter()
+            .any(|val| val.saved.player.0 == user_id || val.saved.player2.0 == user_id)
+        {
+            return true;
+        }
+
+        self.get_current_game(user_id).await.is_some()
+    }
+
+    async fn is_player_already_in_game(
+        &self,
+        http: &Http,
+        interaction: &CommandInteraction,
+    ) -> Result<bool> {
+        let message = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .ephemeral(true)
+                .embed(
+                    CreateEmbed::new()
+                        .title("Start a new game")
+                        .description("You have already in the game. For starting a new game you should use the `/stop` command.")
+                )
+        );
+
+        if self.is_in_game(interaction.user.id).await {
+            interaction.create_response(http, message).await?;
+
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    // The `/leaderboard` command and the refreshing of the pinned tables
+    fn leaderboards(&self) -> Leaderboards<'_> {
+        Leaderboards {
+            pins: &self.pins,
+            stats: &self.stats,
+            theme_choices: &self.theme_choices,
+            render: &self.render,
         }
     }
 
-    async fn generate_end_attachment(
-        &self,
-        session: &mut GameSession,
-        id: u32,
-    ) -> CreateAttachment {
-        match id {
-            0..=2 => {
-                for y in 100 * id..100 * (id + 1) {
-                    for x in 0..300 {
-                        fill_pixel(&mut session.canvas, &self.horizontal_scratch, x, y);
-                    }
-                }
-            }
+    async fn export(&self, http: &Http, interaction: &CommandInteraction) -> Result<()> {
+        let text = {
+            let stats = self.stats.lock().await;
+
+            let record = match option_value(interaction, "game").and_then(|value| value.as_i64()) {
+                Some(id) => stats.game(id as u64),
+                None => stats.last_game_of(interaction.user.id),
+            };
+
+            record.map(|record| (record.id, notation::export(record)))
+        };
+
+        let Some((id, text)) = text else {
+            return reply_ephemeral(http, interaction, "There is no such game.").await;
+        };
+
+        interaction
+            .create_response(
+                http,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .ephemeral(true)
+                        .add_file(CreateAttachment::bytes(
+                            text.into_bytes(),
+                            format!("game-{}.txt", id),
+                        )),
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_queue_policy(&self, http: &Http, interaction: &CommandInteraction) -> Result<()> {
+        let policy = option_value(interaction, "policy")
+            .and_then(|value| value.as_str())
+            .and_then(Policy::from_name)
+            .unwrap_or_default();
+
+        let Some(guild_id) = interaction.guild_id else {
+            return reply_ephemeral(
+                http,
+                interaction,
+                "The queue can be chosen only on a server.",
+            )
+            .await;
+        };
+
+        self.queues.lock().await.set_policy(guild_id, policy);
+
+        let title = match policy {
+            Policy::Fifo => "Players of the server wait in one queue now.",
+            Policy::SameChannel => "Players wait in the queue of their channel now.",
+            Policy::CrossServer => "Players wait in the queue shared by all servers now.",
+        };
+
+        reply_ephemeral(http, interaction, title).await
+    }
+
+    // Running games keep the theme they have started with
+    async fn set_theme(&self, http: &Http, interaction: &CommandInteraction) -> Result<()> {
+        let id = option_value(interaction, "name")
+            .and_then(|value| value.as_str())
+            .unwrap_or_default();
+
+        if !self.render.themes().contains(id) {
+            return reply_ephemeral(http, interaction, "There is no such theme.").await;
+        }
+
+        let name = &self.render.themes().get(id).name;
+
+        if option_value(interaction, "server").and_then(|value| value.as_bool()) != Some(true) {
+            self.theme_choices
+                .lock()
+                .await
+                .set_user(interaction.user.id, id);
+
+            let title = format!("Your games will be drawn in the {} theme.", name);
+            return reply_ephemeral(http, interaction, &title).await;
+        }
+
+        let Some(guild_id) = interaction.guild_id else {
+            return reply_ephemeral(
+                http,
+                interaction,
+                "The theme of the server can be chosen only on a server.",
+            )
+            .await;
+        };
+
+        let is_admin = matches!(
+            interaction.member.as_ref().and_then(|member| member.permissions),
+            Some(permissions) if permissions.manage_guild()
+        );
+
+        if !is_admin {
+            return reply_ephemeral(
+                http,
+                interaction,
+                "Only the administrators of the server can choose its theme.",
+            )
+            .await;
+        }
+
+        self.theme_choices.lock().await.set_guild(guild_id, id);
+
+        let title = format!("{} is the theme of the server now.", name);
+        reply_ephemeral(http, interaction, &title).await
+    }
+
+    async fn show_stats(&self, http: &Http, interaction: &CommandInteraction) -> Result<()> {
+        let (user_id, name) =
+            match option_value(interaction, "user").and_then(|value| value.as_user_id()) {
+                Some(user_id) => (user_id, resolved_name(interaction, user_id)),
+                None => (interaction.user.id, player_name(interaction)),
+            };
+
+        let summary = self.stats.lock().await.summary(user_id);
+        let score = summary.score;
+
+        let mut embed = CreateEmbed::new().title(format!("Statistics of {}", name));
+
+        if score.games() == 0 {
+            embed = embed.description("No games have been played yet.");
+        } else {
+            let streak = match summary.streak {
+                Some((GameResult::Win, count)) => format!("{} won", count),
+                Some((GameResult::Loss, count)) => format!("{} lost", count),
+                Some((GameResult::Draw, count)) => format!("{} drawn", count),
+                None => "-".to_string(),
+            };
+
+            let head_to_head = summary
+                .head_to_head
+                .iter()
+                .take(HEAD_TO_HEAD_LIMIT)
+                .map(|(name, score)| {
+                    format!(
+                        "{}: {} W / {} L / {} D",
+                        name, score.wins, score.losses, score.draws,
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            embed = embed
+                .field("Rating", (summary.rating.round() as i32).to_string(), true)
+                .field("Wins", score.wins.to_string(), true)
+                .field("Losses", score.losses.to_string(), true)
+                .field("Draws", score.draws.to_string(), true)
+                .field("Current streak", streak, true)
+                .field("Best win streak", summary.best_streak.to_string(), true)
+                .field("Head-to-head", head_to_head, false);
+        }
+
+        interaction
+            .create_response(
+                http,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new().embed(embed),
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn stop(&self, http: &Http, interaction: &CommandInteraction) -> Result<()> {
+        let user_id = interaction.user.id;
+
+        let waiting = self
+            .queues
+            .lock()
+            .await
+            .remove(|val| val.player.0 == user_id);
+
+        if let Some(mut val) = waiting {
+            log_panel_error(val.player.1.delete_response(http).await);
+
+            val.player
+                .3
+                .edit(
+                    http,
+                    EditMessage::new().embed(
+                        CreateEmbed::new()
+                            .title(format!("{} doesn't want to play anymore.", val.player.2,)),
+                    ),
+                )
+                .await?;
+
+            return reply_ephemeral(http, interaction, "You have left the queue.").await;
+        }
+
+        if let Some(challenge) =
+            take_challenge(&self.challenges, |val| val.player.0 == user_id).await
+        {
+            let text = format!("{} has withdrawn the challenge.", challenge.player.2);
+            close_challenge(http, challenge, &text).await?;
+
+            return reply_ephemeral(http, interaction, "You have withdrawn the challenge.").await;
+        }
+
+        if self.cancel_restored(http, user_id).await? {
+            return reply_ephemeral(http, interaction, "You have cancelled the game.").await;
+        }
+
+        let Some(original_session) = self.get_current_game(user_id).await else {
+            return reply_ephemeral(http, interaction, "You are not in the game.").await;
+        };
+
+        let mut session = original_session.lock().await;
+        let session = &mut *session;
+
+        // The last move or the clock could have finished the game while we were
+        // waiting for the lock
+        if session.ended {
+            return reply_ephemeral(http, interaction, "You are not in the game.").await;
+        }
+
+        let winner = player_of(session, user_id)
+            .ok_or(Error::NotInGame)?
+            .opponent();
+
+        self.end_off_board(http, session, Some(winner), Ending::Resignation)
+            .await?;
+
+        reply_ephemeral(http, interaction, "You have resigned.").await
+    }
+
+    // The game is lost by resignation or on time, or drawn by agreement, the
+    // board can be in any state
+    async fn end_off_board(
+        &self,
+        http: &Http,
+        session: &mut GameSession,
+        winner: Option<Player>,
+        ending: Ending,
+    ) -> Result<()> {
+        // Drawn first, the game isn't recorded if the bot is too busy to end it
+        let attachment = self.board_attachment(session_scene(session)).await?;
+
+        let (id, changes) = self.record_game(session, winner, ending).await;
+
+        let (loser, winner) = match winner {
+            Some(Player::Second) => (&session.player.2, &session.player2.2),
+            Some(Player::First) => (&session.player2.2, &session.player.2),
+            None => (&session.player.2, &session.player2.2),
+        };
+
+        let description = match ending {
+            Ending::Time => format!("⏰ {} has lost on time. 💥 {} has won! 💥", loser, winner),
+            Ending::Agreement => "🤝 Players have agreed to a draw.".to_string(),
+            _ => format!("🏳️ {} has resigned. 💥 {} has won! 💥", loser, winner),
+        };
+
+        let embed = CreateEmbed::new()
+            .title(format!(
+                "The game between {} and {} has finished!",
+                session.player.2, session.player2.2,
+            ))
+            .description(description)
+            .attachment("canvas.png");
+
+        let message = EditMessage::new()
+            .add_embed(add_record_fields(embed, session, id, changes))
+            .components(vec![generate_replay_action_row(id)])
+            .attachment(attachment);
+
+        self.end_game_with_message(http, session, message).await
+    }
+
+    async fn process_session(&self, http: &Http, session: &mut GameSession) -> Result<()> {
+        if let Some(difficulty) = session.bot {
+            if session.state.turn() == Player::Second {
+                let state = session.state.clone();
+
+                // Search can take a while, so it shouldn't block the event loop
+                let index =
+                    tokio::task::spawn_blocking(move || ai::choose_move(&state, difficulty))
+                        .await
+                        .ok()
+                        .and_then(|result| result.ok())
+                        .flatten()
+                        .ok_or(Error::BotFailed)?;
+
+                let played = session.state.play(index).map_err(|_| Error::BotFailed)?;
+                push_history(session, played);
+
+                if session.state.outcome() != Outcome::InProgress {
+                    self.finish_game(http, session).await?;
+                    return Ok(());
+                }
+
+                session.cursor_pos = initial_cursor(&session.state);
+            }
+        }
+
+        self.save_session(session).await;
+
+        show_panels(http, &self.render, session).await?;
+
+        Ok(())
+    }
+
+    pub async fn component(&self, ctx: Context, component: ComponentInteraction) {
+        // Interaction can be moved into the game, so the copy reports the error
+        let reply_to = PlayerInteraction::Component(component.clone());
+        let custom_id = component.data.custom_id.clone();
+
+        let result = match custom_id.as_str() {
+            "accept" | "decline" => self.answer_challenge(&ctx, component).await,
+            "controls" => self.show_controls(&ctx, component).await,
+            "resume" => self.resume(&ctx, component).await,
+            custom_id if custom_id.starts_with("replay:") => {
+                let id = custom_id["replay:".len()..].parse().unwrap_or_default();
+                self.replay_button(&ctx.http, &component, id).await
+            }
+            _ => self.press(&ctx, component).await,
+        };
+
+        if let Err(err) = result {
+            reply_to.report_error(&ctx.http, err).await;
+        }
+    }
+
+    // Buttons of the game panels
+    async fn press(&self, ctx: &Context, component: ComponentInteraction) -> Result<()> {
+        // We are calling this because we are editing the component
+        // interaction or answering to the original interaction in the progress_game()
+        component
+            .create_response(&ctx.http, CreateInteractionResponse::Acknowledge)
+            .await?;
+
+        let (action, stamp) = Stamp::parse(&component.data.custom_id).ok_or(Error::StaleButton)?;
+
+        let original_session = self
+            .get_current_game(component.user.id)
+            .await
+            .ok_or(Error::NotInGame)?;
+
+        let mut session = original_session.lock().await;
+        let session = &mut *session;
+
+        let player = validate_press(session, component.user.id, action, stamp)?;
+
+        // Move after the flag fall is too late
+        if self.flag_fall(&ctx.http, session).await? {
+            return Ok(());
+        }
+
+        // Buttons are on the panel, so its token is replaced with the fresh one
+        set_interaction(session, player, component.clone());
+
+        match action {
+            "left" => {
+                move_cursor(session, Step::Left);
+
+                update_game_message(&ctx.http, &self.render, &component, session).await?;
+            }
+
+            "down" => {
+                move_cursor(session, Step::Down);
+
+                update_game_message(&ctx.http, &self.render, &component, session).await?;
+            }
+
+            "up" => {
+                move_cursor(session, Step::Up);
+
+                update_game_message(&ctx.http, &self.render, &component, session).await?;
+            }
+
+            "right" => {
+                move_cursor(session, Step::Right);
+
+                update_game_message(&ctx.http, &self.render, &component, session).await?;
+            }
+
+            "back" => {
+                if let CursorPos::LocalCell(board, _) = session.cursor_pos {
+                    session.cursor_pos = CursorPos::Board(board);
+                }
+
+                update_game_message(&ctx.http, &self.render, &component, session).await?;
+            }
+
+            "input" => {
+                let input = match player {
+                    Player::First => &mut session.input.0,
+                    Player::Second => &mut session.input.1,
+                };
+
+                *input = match input {
+                    Input::Cursor => Input::Grid,
+                    Input::Grid => Input::Cursor,
+                };
+
+                update_game_message(&ctx.http, &self.render, &component, session).await?;
+            }
+
+            "send" => {
+                self.send(&ctx.http, &component, session).await?;
+            }
+
+            "resign" => {
+                let winner = player.opponent();
+
+                self.end_off_board(&ctx.http, session, Some(winner), Ending::Resignation)
+                    .await?;
+            }
+
+            "draw" | "takeback" => {
+                let offer = match action {
+                    "draw" => Offer::Draw,
+                    _ => Offer::Takeback,
+                };
+
+                if !can_offer(session, player, offer) {
+                    return Ok(());
+                }
+
+                // The bot doesn't mind taking back, but always plays on
+                if session.bot.is_some() {
+                    if offer == Offer::Takeback {
+                        self.take_back(session, player)?;
+                        self.save_session(session).await;
+                    }
+                } else {
+                    session.offer = Some((player, offer));
+                }
+
+                show_panels(&ctx.http, &self.render, session).await?;
+            }
+
+            "offer_accept" | "offer_decline" => {
+                // Offer could have been cancelled by a move
+                let Some((offered_by, offer)) = session.offer else {
+                    return Ok(());
+                };
+
+                if offered_by == player {
+                    return Ok(());
+                }
+
+                session.offer = None;
+
+                if action == "offer_accept" {
+                    match offer {
+                        Offer::Draw => {
+                            self.end_off_board(&ctx.http, session, None, Ending::Agreement)
+                                .await?;
+
+                            return Ok(());
+                        }
+
+                        Offer::Takeback => {
+                            self.take_back(session, offered_by)?;
+                            self.save_session(session).await;
+                        }
+                    }
+                }
+
+                show_panels(&ctx.http, &self.render, session).await?;
+            }
+
+            action if action.starts_with("cell:") => {
+                let Some(index) = action["cell:".len()..].parse().ok() else {
+                    return Ok(());
+                };
+
+                // Button of the grid is the cursor and the Send button at once
+                session.cursor_pos = match session.cursor_pos {
+                    CursorPos::Cell(_) => CursorPos::Cell(index),
+                    CursorPos::Board(_) => CursorPos::Board(index),
+                    CursorPos::LocalCell(board, _) => CursorPos::LocalCell(board, index),
+                };
+
+                if !can_send(&session.state, session.cursor_pos) {
+                    return Ok(());
+                }
+
+                self.send(&ctx.http, &component, session).await?;
+            }
+
+            _ => return Err(Error::StaleButton),
+        }
+
+        Ok(())
+    }
+
+    async fn send(
+        &self,
+        http: &Http,
+        component: &ComponentInteraction,
+        session: &mut GameSession,
+    ) -> Result<()> {
+        // Choosing a local board of the ultimate game is not a move yet
+        if let CursorPos::Board(board) = session.cursor_pos {
+            session.cursor_pos = CursorPos::LocalCell(board, 4);
+
+            update_game_message(http, &self.render, component, session).await?;
+            return Ok(());
+        }
+
+        let Ok(played) = play_at(&mut session.state, session.cursor_pos) else {
+            // Unreachable in default situation
+            return Ok(());
+        };
+
+        push_history(session, played);
+
+        if session.state.outcome() == Outcome::InProgress {
+            session.cursor_pos = initial_cursor(&session.state);
+
+            self.process_session(http, session).await?;
+        } else {
+            self.finish_game(http, session).await?;
+        }
+
+        Ok(())
+    }
+
+    // Takes back the last move of the player and the reply of the opponent if
+    // there is one, the position is replayed from the start
+    fn take_back(&self, session: &mut GameSession, player: Player) -> Result<()> {
+        let count = if session.state.turn() == player { 2 } else { 1 };
+        let kept = session.history.len().saturating_sub(count);
+
+        let moves: Vec<usize> = session.history[..kept]
+            .iter()
+            .map(|val| val.index)
+            .collect();
+        let (state, _) = replay_moves(session.state.mode(), &moves).ok_or(Error::InvalidMoves)?;
+
+        session.state = state;
+        session.history.truncate(kept);
+        session.version += 1;
+        session.cursor_pos = initial_cursor(&session.state);
+
+        // Turn has gone back to the player, who gets no increment for it
+        if let (1, Some(clock)) = (count, &mut session.clock) {
+            clock.switch();
+        }
+
+        Ok(())
+    }
+
+    async fn get_current_game(&self, user_id: UserId) -> Option<Arc<Mutex<GameSession>>> {
+        self.sessions.lock().await.by_user(user_id).cloned()
+    }
+
+    async fn theme_for(&self, user_id: UserId, guild_id: Option<GuildId>) -> String {
+        self.theme_choices
+            .lock()
+            .await
+            .theme_for(user_id, guild_id)
+            .to_string()
+    }
+
+    async fn board_attachment(&self, scene: Scene) -> Result<CreateAttachment> {
+        Ok(CreateAttachment::bytes(
+            self.render.png(scene.scale(BOARD_SCALE)).await?.to_vec(),
+            "canvas.png",
+        ))
+    }
+
+    // Drawn with the theme of the one who asks
+    async fn generate_replay(&self, id: u64, theme: String) -> Result<EditInteractionResponse> {
+        let Some(record) = self.stats.lock().await.game(id).cloned() else {
+            return Ok(EditInteractionResponse::new()
+                .embed(CreateEmbed::new().title("There is no such game.")));
+        };
+
+        let embed = CreateEmbed::new()
+            .title(format!(
+                "Replay of the game #{} between {} and {}",
+                id, record.player.1, record.player2.1,
+            ))
+            .attachment("replay.gif");
+
+        // Encoding of a long game takes a while
+        let gif = self
+            .render
+            .run(move |renderer| renderer.gif(record.mode, &record.moves, &theme))
+            .await?;
+
+        Ok(EditInteractionResponse::new()
+            .embed(embed)
+            .new_attachment(CreateAttachment::bytes(gif, "replay.gif")))
+    }
+
+    async fn replay(&self, http: &Http, interaction: &CommandInteraction) -> Result<()> {
+        let id = option_value(interaction, "game")
+            .and_then(|value| value.as_i64())
+            .unwrap_or_default() as u64;
+
+        interaction.defer(http).await?;
+
+        let theme = self
+            .theme_for(interaction.user.id, interaction.guild_id)
+            .await;
+        let response = self.generate_replay(id, theme).await?;
+        interaction.edit_response(http, response).await?;
+
+        Ok(())
+    }
+
+    // Replay button of the final message, only the one who pressed it sees the replay
+    async fn replay_button(
+        &self,
+        http: &Http,
+        component: &ComponentInteraction,
+        id: u64,
+    ) -> Result<()> {
+        component.defer_ephemeral(http).await?;
+
+        let theme = self.theme_for(component.user.id, component.guild_id).await;
+        let response = self.generate_replay(id, theme).await?;
+        component.edit_response(http, response).await?;
+
+        Ok(())
+    }
+
+    async fn finish_game(&self, http: &Http, session: &mut GameSession) -> Result<()> {
+        let winner = match session.state.outcome() {
+            Outcome::Win(player, _) => Some(player),
+            _ => None,
+        };
+
+        let scene = match session.state.outcome() {
+            Outcome::Win(_, line) => session_scene(session).win_line(line),
+            _ => session_scene(session),
+        };
+
+        let attachment = self.board_attachment(scene).await?;
+
+        let (id, changes) = self.record_game(session, winner, Ending::Board).await;
+
+        let description = match session.state.outcome() {
+            Outcome::Win(Player::First, _) => format!("💥 {} has won! 💥", session.player.2),
+            Outcome::Win(Player::Second, _) => format!("💥 {} has won! 💥", session.player2.2),
+            Outcome::Draw => "No one wins!".to_string(),
+            Outcome::InProgress => unreachable!(),
+        };
+
+        let embed = CreateEmbed::new()
+            .title(format!(
+                "The game between {} and {} has finished!",
+                session.player.2, session.player2.2,
+            ))
+            .description(description)
+            .attachment("canvas.png");
+
+        let message = EditMessage::new()
+            .add_embed(add_record_fields(embed, session, id, changes))
+            .components(vec![generate_replay_action_row(id)])
+            .attachment(attachment);
+
+        self.end_game_with_message(http, session, message).await
+    }
+
+    // Returns the id of the game and the changes of the ratings of both players
+    async fn record_game(
+        &self,
+        session: &GameSession,
+        winner: Option<Player>,
+        ending: Ending,
+    ) -> (u64, Option<(Change, Change)>) {
+        let mut stats = self.stats.lock().await;
+        let id = stats.next_id();
+
+        let record = GameRecord {
+            id,
+            player: (session.player.0, session.player.2.clone()),
+            player2: (session.player2.0, session.player2.2.clone()),
+            mode: session.state.mode(),
+            winner,
+            ending,
+            moves: session.history.iter().map(|val| val.index).collect(),
+            timestamp: stats::now(),
+            bot: session.bot.is_some(),
+            guild: session.player.1.guild_id(),
+            times: session.history.iter().map(|val| val.time).collect(),
+            time_control: session.clock.as_ref().map(Clock::control),
+        };
+
+        if let (false, Some(guild_id)) = (record.bot, record.guild) {
+            self.pins.lock().await.mark_outdated(guild_id);
+        }
+
+        let changes = stats.rate(&record);
+        stats.record(record);
+
+        (id, changes)
+    }
+
+    async fn end_game_with_message(
+        &self,
+        http: &Http,
+        session: &mut GameSession,
+        message: EditMessage,
+    ) -> Result<()> {
+        session.ended = true;
+
+        self.saved_sessions.lock().await.remove(session.player.0);
+
+        // Game is over even if Discord fails to show it
+        self.sessions.lock().await.remove(session.player.3.id);
+
+        log_panel_error(session.player.1.delete_response(http).await);
+        if let Some(interaction) = &session.player2.1 {
+            log_panel_error(interaction.delete_response(http).await);
+        }
+
+        if let Some(val) = &mut session.player2.3 {
+            val.edit(http, message.clone()).await?;
+        }
+
+        session.player.3.edit(http, message).await?;
+
+        Ok(())
+    }
+}
+
+fn describe_mode(mode: Mode) -> String {
+    match mode {
+        Mode::Classic(rules) if rules.is_classic() => String::new(),
+        Mode::Classic(rules) => {
+            format!(" on {0}×{0} board with {1} in a row", rules.size, rules.win)
+        }
+        Mode::Ultimate => " in the ultimate mode".to_string(),
+    }
+}
+
+fn describe_time_control(interaction: &CommandInteraction) -> String {
+    time_control_from_options(interaction).map_or(String::new(), |control| {
+        format!(" with {} clock", control.name())
+    })
+}
+
+fn describe_queue(key: QueueKey) -> &'static str {
+    match key {
+        QueueKey::Channel(_) => {
+            "You can join to him/her/them by using the `/play queue:channel` command in this channel."
+        }
+        QueueKey::Guild(_) => "You can join to him/her/them by using the `/play` command.",
+        QueueKey::Global => {
+            "You can join to him/her/them from any server by using the `/play queue:global` command."
+        }
+    }
+}
+
+fn player_name(interaction: &CommandInteraction) -> String {
+    member_name(interaction.member.as_deref(), &interaction.user)
+}
+
+fn add_record_fields(
+    embed: CreateEmbed,
+    session: &GameSession,
+    id: u64,
+    changes: Option<(Change, Change)>,
+) -> CreateEmbed {
+    let embed = embed.footer(CreateEmbedFooter::new(format!(
+        "Game #{0}, use /export game:{0} to download it",
+        id,
+    )));
+
+    let Some((change, change2)) = changes else {
+        return embed;
+    };
+
+    let describe = |change: Change| format!("{:+} ({})", change.delta(), change.rating());
+
+    embed
+        .field(&session.player.2, describe(change), true)
+        .field(&session.player2.2, describe(change2), true)
+}
+
+// Remaining time of both players, Discord counts down the running one
+fn add_clock_fields(embed: CreateEmbed, session: &GameSession) -> CreateEmbed {
+    let Some(clock) = &session.clock else {
+        return embed;
+    };
+
+    let players = [
+        (Player::First, &session.player.2),
+        (Player::Second, &session.player2.2),
+    ];
+
+    players.into_iter().fold(embed, |embed, (player, name)| {
+        let remaining = clock.remaining(player);
+
+        let value = if player == session.state.turn() {
+            format!(
+                "{}, runs out <t:{}:R>",
+                clock::format(remaining),
+                stats::now() + remaining.as_secs(),
+            )
+        } else {
+            clock::format(remaining)
+        };
+
+        embed.field(format!("⏱️ {}", name), value, true)
+    })
+}
+
+fn resolved_name(interaction: &CommandInteraction, user_id: UserId) -> String {
+    let resolved = &interaction.data.resolved;
+
+    let nick = resolved
+        .members
+        .get(&user_id)
+        .and_then(|member| member.nick.clone());
+    let name = resolved.users.get(&user_id).map(|user| user.name.clone());
+
+    nick.or(name).unwrap_or_else(|| user_id.to_string())
+}
+
+fn member_name(member: Option<&Member>, user: &User) -> String {
+    match member {
+        Some(val) => val.nick.clone().unwrap_or_else(|| user.name.clone()),
+        None => user.name.clone(),
+    }
+}
+
+fn is_valid_opponent(interaction: &CommandInteraction, opponent: UserId) -> bool {
+    let is_bot = matches!(
+        interaction.data.resolved.users.get(&opponent),
+        Some(user) if user.bot
+    );
 
-            3..=5 => {
-                for y in 0..300 {
-                    for x in 100 * (id - 3)..100 * (id - 2) {
-                        fill_pixel(&mut session.canvas, &self.vertical_scratch, x, y);
-                    }
-                }
-            }
+    opponent != interaction.user.id && !is_bot
+}
 
-            6 => {
-                for y in 0..300 {
-                    for x in 0..300 {
-                        fill_pixel(&mut session.canvas, &self.diagonal_scratch_1, x, y);
-                    }
-                }
-            }
+fn mode_from_options(interaction: &CommandInteraction) -> Option<Mode> {
+    let option = |name| option_value(interaction, name);
 
-            7 => {
-                for y in 0..300 {
-                    for x in 0..300 {
-                        fill_pixel(&mut session.canvas, &self.diagonal_scratch_2, x, y);
-                    }
-                }
-            }
+    if option("mode").and_then(|value| value.as_str()) == Some("ultimate") {
+        return Some(Mode::Ultimate);
+    }
 
-            _ => unreachable!(),
+    let integer = |name| {
+        option(name)
+            .and_then(|value| value.as_i64())
+            .map(|value| value as usize)
+    };
+
+    let size = integer("size").unwrap_or(Rules::default().size);
+
+    let rules = match integer("win") {
+        Some(win) => Rules::new(size, win),
+        None => Rules::with_default_win(size),
+    };
+
+    rules.map(Mode::Classic)
+}
+
+fn time_control_from_options(interaction: &CommandInteraction) -> Option<TimeControl> {
+    option_value(interaction, "time")
+        .and_then(|value| value.as_str())
+        .and_then(TimeControl::from_name)
+}
+
+fn initial_cursor(state: &Variant) -> CursorPos {
+    match state {
+        Variant::Classic(state) => CursorPos::Cell(state.board().center()),
+        Variant::Ultimate(state) => match state.active() {
+            Some(board) => CursorPos::LocalCell(board, 4),
+            None => CursorPos::Board(4),
+        },
+    }
+}
+
+fn cursor_neighbour(state: &Variant, cursor_pos: CursorPos, step: Step) -> Option<CursorPos> {
+    match (state, cursor_pos) {
+        (Variant::Classic(state), CursorPos::Cell(cell)) => {
+            state.board().neighbour(cell, step).map(CursorPos::Cell)
         }
 
-        generate_attachment_rgb8(&session.canvas, "canvas.png")
+        (Variant::Ultimate(state), CursorPos::Board(board)) => {
+            state.meta().neighbour(board, step).map(CursorPos::Board)
+        }
+
+        (Variant::Ultimate(state), CursorPos::LocalCell(board, cell)) => state
+            .board(board)
+            .neighbour(cell, step)
+            .map(|cell| CursorPos::LocalCell(board, cell)),
+
+        _ => unreachable!(),
     }
+}
 
-    async fn end_game_with_message(
-        &self,
-        http: &Http,
-        session: &mut GameSession,
-        original_session: &Arc<Mutex<GameSession>>,
-        message: EditMessage,
-    ) {
-        session.player.1.delete_response(http).await.unwrap();
-        session.player2.1.delete_response(http).await.unwrap();
+fn can_send(state: &Variant, cursor_pos: CursorPos) -> bool {
+    match (state, cursor_pos) {
+        (Variant::Classic(state), CursorPos::Cell(cell)) => state.board().is_empty_at(cell),
 
-        if let Some(val) = &mut session.player2.3 {
-            val.edit(http, message.clone()).await.unwrap();
+        (Variant::Ultimate(state), CursorPos::Board(board)) => state.can_play_in(board),
+
+        (Variant::Ultimate(state), CursorPos::LocalCell(board, cell)) => {
+            state.can_play_in(board) && state.board(board).is_empty_at(cell)
         }
 
-        session.player.3.edit(http, message).await.unwrap();
+        _ => unreachable!(),
+    }
+}
 
-        let mut games = self.sessions.lock().await;
-        let pos = games
-            .iter()
-            .position(|val| Arc::ptr_eq(val, original_session));
-        games.swap_remove(pos.unwrap());
+// Going back to the choice of a local board is only possible if it is free
+fn can_go_back(state: &Variant, cursor_pos: CursorPos) -> bool {
+    match (state, cursor_pos) {
+        (Variant::Ultimate(state), CursorPos::LocalCell(..)) => state.active().is_none(),
+        _ => false,
     }
 }
 
-fn draw_new_game_canvas() -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-    let mut canvas = ImageBuffer::new(300, 300);
+fn play_at(state: &mut Variant, cursor_pos: CursorPos) -> std::result::Result<Move, MoveError> {
+    match (state, cursor_pos) {
+        (Variant::Classic(state), CursorPos::Cell(cell)) => state.play(cell),
+        (Variant::Ultimate(state), CursorPos::LocalCell(board, cell)) => state.play(board, cell),
+        _ => unreachable!(),
+    }
+}
+
+fn describe_last_move(session: &GameSession) -> Option<String> {
+    let last = session.history.last()?;
 
-    // Background
-    draw_filled_rect_mut(&mut canvas, Rect::at(0, 0).of_size(300, 300), BACKGROUND);
+    let name = match last.player {
+        Player::First => &session.player.2,
+        Player::Second => &session.player2.2,
+    };
 
-    draw_filled_rect_mut(&mut canvas, Rect::at(98, 0).of_size(4, 300), GRAY);
+    Some(format!(
+        "Last move: {} by {}.",
+        notation::square(session.state.mode(), last.index),
+        name,
+    ))
+}
 
-    draw_filled_rect_mut(&mut canvas, Rect::at(198, 0).of_size(4, 300), GRAY);
+// Board as everyone sees it, the panels add the cursor
+fn session_scene(session: &GameSession) -> Scene {
+    let scene = Scene::new(&session.state, &session.theme).labels(session.labels);
 
-    draw_filled_rect_mut(&mut canvas, Rect::at(0, 98).of_size(300, 4), GRAY);
+    match session.history.last() {
+        Some(last) => scene.last_move(last.index),
+        None => scene,
+    }
+}
 
-    draw_filled_rect_mut(&mut canvas, Rect::at(0, 198).of_size(300, 4), GRAY);
+fn input_of(session: &GameSession, player: Player) -> Input {
+    match player {
+        Player::First => session.input.0,
+        Player::Second => session.input.1,
+    }
+}
 
-    canvas
+// Both the classic 3x3 board and every stage of the ultimate board fit in
+// three rows of buttons
+fn has_grid(state: &Variant) -> bool {
+    match state {
+        Variant::Classic(state) => state.board().size() == 3,
+        Variant::Ultimate(_) => true,
+    }
 }
 
-async fn show_wait_and_common_message(
-    http: &Http,
-    interaction: &CommandInteraction,
-    canvas: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-    player_name: &str,
-    player2_name: &str,
-    common_message: &mut Message,
-    common_message2: Option<&mut Message>,
-) {
-    let embed = CreateEmbed::new()
-        .title("Game in process")
-        .description("Waiting for your turn.")
-        .thumbnail("attachment://thumbnail.png");
-
-    let action_row = generate_disabled_action_row();
-    let attachment = generate_attachment_rgb8(canvas, "canvas.png");
-
-    interaction
-        .edit_response(
+// Position and history of the saved game, none if the file is corrupted:
+// a move isn't legal or has no time
+fn restore_state(saved: &SavedSession) -> Option<(Variant, Vec<HistoryMove>)> {
+    if saved.moves.len() != saved.times.len() {
+        return None;
+    }
+
+    let (state, played) = replay_moves(saved.mode, &saved.moves)?;
+
+    let history = played
+        .iter()
+        .zip(&saved.times)
+        .map(|(played, time)| HistoryMove {
+            player: played.player,
+            index: played.cell,
+            time: *time,
+        })
+        .collect();
+
+    Some((state, history))
+}
+
+// Every move also presses the clock and cancels the offer
+fn push_history(session: &mut GameSession, played: Move) {
+    session.history.push(HistoryMove {
+        player: played.player,
+        index: played.cell,
+        time: session.started.elapsed().as_millis() as u64,
+    });
+    session.version += 1;
+
+    if let Some(clock) = &mut session.clock {
+        clock.press();
+    }
+
+    session.offer = None;
+}
+
+// Position after the moves from the start and the played moves, none if a
+// move isn't legal
+fn replay_moves(mode: Mode, moves: &[usize]) -> Option<(Variant, Vec<Move>)> {
+    let mut state = Variant::new(mode);
+
+    let played = moves
+        .iter()
+        .map(|index| state.play(*index).ok())
+        .collect::<Option<_>>()?;
+
+    Some((state, played))
+}
+
+fn set_interaction(session: &mut GameSession, player: Player, component: ComponentInteraction) {
+    let interaction = PlayerInteraction::Component(component);
+
+    match player {
+        Player::First => session.player.1 = interaction,
+        Player::Second => session.player2.1 = Some(interaction),
+    }
+}
+
+// `None` for the ones who don't play the game
+fn player_of(session: &GameSession, user_id: UserId) -> Option<Player> {
+    if session.player.0 == user_id {
+        Some(Player::First)
+    } else if session.player2.0 == user_id && session.bot.is_none() {
+        Some(Player::Second)
+    } else {
+        None
+    }
+}
+
+// Press is taken only from a player of the game, on the latest panel of it.
+// Board and input buttons are for the player on turn only, the offers and
+// the resignation are for both players at any time.
+fn validate_press(
+    session: &GameSession,
+    user_id: UserId,
+    action: &str,
+    stamp: Stamp,
+) -> Result<Player> {
+    let player = player_of(session, user_id).ok_or(Error::NotInGame)?;
+
+    if stamp.game != Stamp::of(session).game {
+        return Err(Error::StaleButton);
+    }
+
+    if session.ended {
+        return Err(Error::GameFinished);
+    }
+
+    if stamp.version != session.version {
+        return Err(Error::StaleButton);
+    }
+
+    let is_offer = matches!(
+        action,
+        "resign" | "draw" | "takeback" | "offer_accept" | "offer_decline"
+    );
+
+    if !is_offer && player != session.state.turn() {
+        return Err(Error::NotYourTurn);
+    }
+
+    Ok(player)
+}
+
+// One offer at a time, and only own moves can be taken back
+fn can_offer(session: &GameSession, player: Player, offer: Offer) -> bool {
+    if session.offer.is_some() {
+        return false;
+    }
+
+    match offer {
+        Offer::Draw => session.bot.is_none(),
+        Offer::Takeback => session.history.iter().any(|val| val.player == player),
+    }
+}
+
+fn describe_offer(session: &GameSession, player: Player) -> Option<String> {
+    let (offered_by, offer) = session.offer?;
+
+    if offered_by == player {
+        return Some("Waiting for the answer to your offer.".to_string());
+    }
+
+    let name = match offered_by {
+        Player::First => &session.player.2,
+        Player::Second => &session.player2.2,
+    };
+
+    Some(match offer {
+        Offer::Draw => format!("{} offers a draw.", name),
+        Offer::Takeback => format!("{} asks to take back the last move.", name),
+    })
+}
+
+fn move_cursor(session: &mut GameSession, step: Step) {
+    if let Some(cursor_pos) = cursor_neighbour(&session.state, session.cursor_pos, step) {
+        session.cursor_pos = cursor_pos;
+    }
+}
+
+async fn take_challenge(
+    challenges: &Mutex<Vec<Challenge>>,
+    predicate: impl Fn(&Challenge) -> bool,
+) -> Option<Challenge> {
+    let mut challenges = challenges.lock().await;
+    let pos = challenges.iter().position(predicate)?;
+
+    Some(challenges.swap_remove(pos))
+}
+
+// Removes the buttons and the panel of the challenger when no game has been started
+async fn close_challenge(http: &Http, challenge: Challenge, text: &str) -> Result<()> {
+    let (_, interaction, _, mut message) = challenge.player;
+
+    interaction.delete_response(http).await?;
+
+    message
+        .edit(
             http,
-            EditInteractionResponse::new()
-                .add_embed(embed)
-                .components(vec![action_row])
-                .new_attachment(attachment.clone()),
+            EditMessage::new()
+                .embed(CreateEmbed::new().title(text))
+                .components(vec![]),
         )
-        .await
-        .unwrap();
+        .await?;
+
+    Ok(())
+}
+
+// Discord answers 404 only for the deleted messages and channels, the other
+// errors can go away
+fn is_unknown_message(err: &serenity::Error) -> bool {
+    matches!(
+        err,
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response))
+            if response.status_code == StatusCode::NOT_FOUND
+    )
+}
+
+// Tokens of the interactions expire in 15 minutes, so the panels of slow
+// games can't be edited after that. Players get new ones with the button of
+// the game message, so the error is only logged.
+fn log_panel_error<T>(result: serenity::Result<T>) {
+    if let Err(err) = result {
+        eprintln!("Failed to update the game panel: {err:?}");
+    }
+}
+
+async fn reply_component(http: &Http, component: &ComponentInteraction, title: &str) -> Result<()> {
+    component
+        .create_response(
+            http,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .ephemeral(true)
+                    .embed(CreateEmbed::new().title(title)),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn show_panels(http: &Http, render: &RenderPool, session: &mut GameSession) -> Result<()> {
+    match session.state.turn() {
+        Player::First => {
+            show_game_message(http, render, &session.player.1, session).await?;
+
+            show_wait_and_common_message(http, render, session, Player::Second).await?;
+        }
+        Player::Second => {
+            // The bot is on turn only if it has failed to move
+            let interaction = session.player2.1.as_ref().ok_or(Error::BotFailed)?;
+
+            show_game_message(http, render, interaction, session).await?;
+
+            show_wait_and_common_message(http, render, session, Player::First).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn show_wait_and_common_message(
+    http: &Http,
+    render: &RenderPool,
+    session: &mut GameSession,
+    waiting: Player,
+) -> Result<()> {
+    // The bot has no message to show
+    let interaction = match waiting {
+        Player::First => Some(&session.player.1),
+        Player::Second => session.player2.1.as_ref(),
+    };
+
+    let last_move = describe_last_move(session);
+
+    let description = match describe_offer(session, waiting) {
+        Some(offer) => format!("Waiting for your turn.\n{}", offer),
+        None => "Waiting for your turn.".to_string(),
+    };
+
+    let embed = add_clock_fields(
+        CreateEmbed::new()
+            .title("Game in process")
+            .description(description)
+            .thumbnail("attachment://thumbnail.png"),
+        session,
+    );
+
+    let mut action_rows = if input_of(session, waiting) == Input::Grid && has_grid(&session.state) {
+        generate_grid_action_rows(&session.state, session.cursor_pos, Stamp::of(session), true)
+    } else {
+        vec![generate_disabled_action_row()]
+    };
+
+    action_rows.push(CreateActionRow::Buttons(generate_offer_buttons(
+        session, waiting,
+    )));
+    let scene = session_scene(session).scale(BOARD_SCALE);
+    let png = render.png(scene).await?;
+    let attachment = CreateAttachment::bytes(png.to_vec(), "canvas.png");
+
+    if let Some(interaction) = interaction {
+        log_panel_error(
+            interaction
+                .edit_response(
+                    http,
+                    EditInteractionResponse::new()
+                        .add_embed(embed)
+                        .components(action_rows)
+                        .new_attachment(attachment.clone()),
+                )
+                .await,
+        );
+    }
 
     let edited_message = EditMessage::new()
-        .embed(
+        .embed(add_clock_fields(
             CreateEmbed::new()
                 .title(format!(
                     "Game between {} and {} in the progress!",
-                    player_name, player2_name,
+                    session.player.2, session.player2.2,
+                ))
+                .description(format!(
+                    "{}You can play this game too by using the `/play` command.\n\
+                     Players can get their controls again with the button below.",
+                    last_move.map_or(String::new(), |val| val + "\n"),
                 ))
-                .description("You can play this game too by using the `/play` command.")
                 .attachment("canvas.png"),
-        )
+            session,
+        ))
+        .components(vec![generate_controls_action_row()])
         .attachment(attachment);
 
-    if let Some(val) = common_message2 {
-        val.edit(http, edited_message.clone()).await.unwrap();
+    if let Some(val) = &mut session.player2.3 {
+        val.edit(http, edited_message.clone()).await?;
     }
 
-    common_message.edit(http, edited_message).await.unwrap();
+    session.player.3.edit(http, edited_message).await?;
+
+    Ok(())
 }
 
 async fn show_game_message(
     http: &Http,
-    interaction: &CommandInteraction,
-    cursor_pos: usize,
-    map: &[GameCell],
-    canvas: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-) {
-    let embed = CreateEmbed::new()
-        .title("Your turn")
-        .description("Press arrows buttons for moving selection square.");
-
-    let action_row = if map[cursor_pos] != GameCell::None {
-        generate_game_action_row(true, cursor_pos)
-    } else {
-        generate_game_action_row(false, cursor_pos)
-    };
-
-    let mut cloned = canvas.clone();
-
-    draw_select_outline(&mut cloned, cursor_pos);
+    render: &RenderPool,
+    interaction: &PlayerInteraction,
+    session: &GameSession,
+) -> Result<()> {
+    let message = generate_game_message(render, session).await?;
+    log_panel_error(interaction.edit_response(http, message).await);
 
-    interaction
-        .edit_response(
-            http,
-            EditInteractionResponse::new()
-                .embed(embed)
-                .components(vec![action_row])
-                .new_attachment(generate_attachment_rgb8(&cloned, "canvas.png")),
-        )
-        .await
-        .unwrap();
+    Ok(())
 }
 
 async fn update_game_message(
     http: &Http,
+    render: &RenderPool,
     interaction: &ComponentInteraction,
     session: &GameSession,
-) {
-    let embed = CreateEmbed::new()
-        .title("Your turn")
-        .description("Press arrows buttons for moving selection square.");
-
-    let action_row = if session.map[session.cursor_pos] != GameCell::None {
-        generate_game_action_row(true, session.cursor_pos)
-    } else {
-        generate_game_action_row(false, session.cursor_pos)
-    };
-
-    let mut cloned = session.canvas.clone();
-
-    draw_select_outline(&mut cloned, session.cursor_pos);
+) -> Result<()> {
+    let message = generate_game_message(render, session).await?;
+    interaction.edit_response(http, message).await?;
 
-    interaction
-        .edit_response(
-            http,
-            EditInteractionResponse::new()
-                .embed(embed)
-                .components(vec![action_row])
-                .new_attachment(generate_attachment_rgb8(&cloned, "canvas.png")),
-        )
-        .await
-        .unwrap();
+    Ok(())
 }
 
-fn draw_select_outline(canvas: &mut ImageBuffer<Rgb<u8>, Vec<u8>>, cell: usize) {
-    match cell {
-        0 => {
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 + 98, CELLS[cell].1).of_size(4, 102),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0, CELLS[cell].1 + 98).of_size(98, 4),
-                RED,
-            );
-        }
+async fn generate_game_message(
+    render: &RenderPool,
+    session: &GameSession,
+) -> Result<EditInteractionResponse> {
+    let (state, cursor_pos) = (&session.state, session.cursor_pos);
+    let grid = input_of(session, state.turn()) == Input::Grid && has_grid(state);
+
+    let mut description = match (grid, cursor_pos) {
+        (true, CursorPos::Board(_)) => "Press the button of the board to play in.",
+        (true, _) => "Press the button of the cell to make a move.",
+        (false, CursorPos::Board(_)) => "Press arrows buttons for choosing the board to play in.",
+        (false, _) => "Press arrows buttons for moving selection square.",
+    }
+    .to_string();
 
-        1 => {
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 + 98, CELLS[cell].1).of_size(4, 102),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 + 98).of_size(100, 4),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1).of_size(4, 98),
-                RED,
-            );
-        }
+    if let Some(offer) = describe_offer(session, state.turn()) {
+        description = format!("{}\n{}", description, offer);
+    }
 
-        2 => {
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 + 98).of_size(102, 4),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1).of_size(4, 98),
-                RED,
-            );
+    let embed = add_clock_fields(
+        CreateEmbed::new()
+            .title("Your turn")
+            .description(description),
+        session,
+    );
+
+    // Grid has no cursor, only the local board it stands for is outlined
+    let scene = match (grid, cursor_pos) {
+        (false, _) => session_scene(session).outline(cursor_pos),
+        (true, CursorPos::LocalCell(board, _)) => {
+            session_scene(session).outline(CursorPos::Board(board))
         }
+        (true, _) => session_scene(session),
+    };
 
-        3 => {
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0, CELLS[cell].1 - 2).of_size(102, 4),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 + 98, CELLS[cell].1 + 2).of_size(4, 100),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0, CELLS[cell].1 + 98).of_size(98, 4),
-                RED,
-            );
-        }
+    let stamp = Stamp::of(session);
 
-        4 => {
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 - 2).of_size(104, 4),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 + 98, CELLS[cell].1 + 2).of_size(4, 100),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 + 98).of_size(100, 4),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 + 2).of_size(4, 96),
-                RED,
-            );
-        }
+    let mut action_rows = if grid {
+        generate_grid_action_rows(state, cursor_pos, stamp, false)
+    } else {
+        generate_game_action_rows(state, cursor_pos, stamp)
+    };
 
-        5 => {
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 - 2).of_size(102, 4),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 + 98).of_size(102, 4),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 + 2).of_size(4, 96),
-                RED,
-            );
-        }
+    // Row of the offers has the switch of the input too, all of the rows
+    // are taken by the grid of the ultimate board
+    let mut buttons = generate_offer_buttons(session, state.turn());
 
-        6 => {
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0, CELLS[cell].1 - 2).of_size(102, 4),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 + 98, CELLS[cell].1 + 2).of_size(4, 98),
-                RED,
-            );
-        }
+    if has_grid(state) && session.offer.is_none() {
+        buttons.push(generate_input_button(grid, stamp));
+    }
 
-        7 => {
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 - 2).of_size(104, 4),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 + 98, CELLS[cell].1 + 2).of_size(4, 98),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 + 2).of_size(4, 98),
-                RED,
-            );
-        }
+    action_rows.push(CreateActionRow::Buttons(buttons));
 
-        8 => {
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 - 2).of_size(102, 4),
-                RED,
-            );
-
-            draw_filled_rect_mut(
-                canvas,
-                Rect::at(CELLS[cell].0 - 2, CELLS[cell].1 + 2).of_size(4, 98),
-                RED,
-            );
-        }
+    let png = render.png(scene.scale(BOARD_SCALE)).await?;
 
-        _ => unreachable!(),
-    }
+    Ok(EditInteractionResponse::new()
+        .embed(embed)
+        .components(action_rows)
+        .new_attachment(CreateAttachment::bytes(png.to_vec(), "canvas.png")))
 }
 
 fn generate_disabled_action_row() -> CreateActionRow {
@@ -952,101 +2875,191 @@ fn generate_disabled_action_row() -> CreateActionRow {
     action_row
 }
 
-fn generate_game_action_row(send_disabled: bool, cursor_position: usize) -> CreateActionRow {
-    let mut left = CreateButton::new("left")
-        .label("←")
-        .style(ButtonStyle::Secondary);
+// Buttons of the cells, or of the local boards while choosing one of them
+fn generate_grid_action_rows(
+    state: &Variant,
+    cursor_pos: CursorPos,
+    stamp: Stamp,
+    disabled: bool,
+) -> Vec<CreateActionRow> {
+    let button = |index: usize| {
+        let (taken, target) = match (state, cursor_pos) {
+            (Variant::Classic(state), _) => (state.board().cell(index), CursorPos::Cell(index)),
+
+            (Variant::Ultimate(state), CursorPos::Board(_)) => {
+                let taken = match state.local_outcome(index) {
+                    Outcome::Win(player, _) => Cell::Taken(player),
+                    _ => Cell::Empty,
+                };
 
-    if [0, 3, 6].contains(&cursor_position) {
-        left = left.disabled(true);
-    }
+                (taken, CursorPos::Board(index))
+            }
 
-    let mut down = CreateButton::new("down")
-        .label("↓")
-        .style(ButtonStyle::Secondary);
+            (Variant::Ultimate(state), CursorPos::LocalCell(board, _)) => (
+                state.board(board).cell(index),
+                CursorPos::LocalCell(board, index),
+            ),
 
-    if cursor_position >= 6 {
-        down = down.disabled(true);
-    }
+            _ => unreachable!(),
+        };
 
-    let mut up = CreateButton::new("up")
-        .label("↑")
-        .style(ButtonStyle::Secondary);
+        let (label, style) = match taken {
+            Cell::Taken(Player::First) => ("X", ButtonStyle::Primary),
+            Cell::Taken(Player::Second) => ("O", ButtonStyle::Danger),
+            Cell::Empty => (BLANK_LABEL, ButtonStyle::Secondary),
+        };
+
+        CreateButton::new(stamp.id(&format!("cell:{}", index)))
+            .label(label)
+            .style(style)
+            .disabled(disabled || !can_send(state, target))
+    };
+
+    let mut action_rows: Vec<CreateActionRow> = (0..3)
+        .map(|row| CreateActionRow::Buttons((row * 3..row * 3 + 3).map(button).collect()))
+        .collect();
 
-    if cursor_position <= 2 {
-        up = up.disabled(true);
+    if !disabled && can_go_back(state, cursor_pos) {
+        let back = CreateButton::new(stamp.id("back"))
+            .label("Back to boards")
+            .style(ButtonStyle::Secondary);
+
+        action_rows.push(CreateActionRow::Buttons(vec![back]));
     }
 
-    let mut right = CreateButton::new("right")
-        .label("→")
-        .style(ButtonStyle::Secondary);
+    action_rows
+}
+
+fn generate_input_button(grid: bool, stamp: Stamp) -> CreateButton {
+    let label = if grid { "Use arrows" } else { "Use grid" };
+
+    CreateButton::new(stamp.id("input"))
+        .label(label)
+        .style(ButtonStyle::Secondary)
+}
 
-    if [2, 5, 8].contains(&cursor_position) {
-        right = right.disabled(true);
+// Answer to the offer of the opponent, or the buttons to make one
+fn generate_offer_buttons(session: &GameSession, player: Player) -> Vec<CreateButton> {
+    let stamp = Stamp::of(session);
+
+    if let Some((offered_by, offer)) = session.offer {
+        if offered_by != player {
+            let label = match offer {
+                Offer::Draw => "Accept draw",
+                Offer::Takeback => "Allow takeback",
+            };
+
+            let accept = CreateButton::new(stamp.id("offer_accept"))
+                .label(label)
+                .style(ButtonStyle::Success);
+
+            let decline = CreateButton::new(stamp.id("offer_decline"))
+                .label("Decline")
+                .style(ButtonStyle::Danger);
+
+            return vec![accept, decline];
+        }
     }
 
-    let send = CreateButton::new("send")
-        .label("Send")
-        .style(ButtonStyle::Primary)
-        .disabled(send_disabled);
+    let resign = CreateButton::new(stamp.id("resign"))
+        .label("Resign")
+        .style(ButtonStyle::Danger);
 
-    let action_row = CreateActionRow::Buttons(vec![left, down, up, right, send]);
+    let draw = CreateButton::new(stamp.id("draw"))
+        .label("Offer draw")
+        .style(ButtonStyle::Secondary)
+        .disabled(!can_offer(session, player, Offer::Draw));
 
-    action_row
+    let takeback = CreateButton::new(stamp.id("takeback"))
+        .label("Request takeback")
+        .style(ButtonStyle::Secondary)
+        .disabled(!can_offer(session, player, Offer::Takeback));
+
+    vec![resign, draw, takeback]
 }
 
-fn generate_attachment(
-    image: &[u8],
-    width: u32,
-    height: u32,
-    name: &'static str,
-    color_type: ColorType,
-) -> CreateAttachment {
-    let buffer = Vec::new();
-    let cursor = Cursor::new(buffer);
-    let mut buffered_writer = BufWriter::new(cursor);
-
-    image::write_buffer_with_format(
-        &mut buffered_writer,
-        &image,
-        width,
-        height,
-        color_type,
-        ImageOutputFormat::Png,
-    )
-    .expect("failed to write in buffer");
+fn generate_controls_action_row() -> CreateActionRow {
+    let controls = CreateButton::new("controls")
+        .label("Show controls")
+        .style(ButtonStyle::Secondary);
 
-    let buffer = buffered_writer.into_inner().unwrap().into_inner();
+    CreateActionRow::Buttons(vec![controls])
+}
+
+fn generate_resume_action_row() -> CreateActionRow {
+    let resume = CreateButton::new("resume")
+        .label("Resume")
+        .style(ButtonStyle::Success);
+
+    CreateActionRow::Buttons(vec![resume])
+}
 
-    CreateAttachment::bytes(buffer, name)
+fn generate_replay_action_row(id: u64) -> CreateActionRow {
+    let replay = CreateButton::new(format!("replay:{}", id))
+        .label("Replay")
+        .style(ButtonStyle::Secondary);
+
+    CreateActionRow::Buttons(vec![replay])
 }
 
-fn generate_attachment_rgb8(
-    image: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-    name: &'static str,
-) -> CreateAttachment {
-    generate_attachment(image, image.width(), image.height(), name, ColorType::Rgb8)
+fn generate_challenge_action_row() -> CreateActionRow {
+    let accept = CreateButton::new("accept")
+        .label("Accept")
+        .style(ButtonStyle::Success);
+
+    let decline = CreateButton::new("decline")
+        .label("Decline")
+        .style(ButtonStyle::Danger);
+
+    CreateActionRow::Buttons(vec![accept, decline])
 }
 
-fn fill_pixel(
-    canvas: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    scratch: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-    x: u32,
-    y: u32,
-) {
-    let pixel = canvas.get_pixel(x, y).0;
-    let pixel2 = scratch.get_pixel(x, y).0;
+fn generate_game_action_rows(
+    state: &Variant,
+    cursor_pos: CursorPos,
+    stamp: Stamp,
+) -> Vec<CreateActionRow> {
+    let is_blocked = |step| cursor_neighbour(state, cursor_pos, step).is_none();
+
+    let left = CreateButton::new(stamp.id("left"))
+        .label("←")
+        .style(ButtonStyle::Secondary)
+        .disabled(is_blocked(Step::Left));
+
+    let down = CreateButton::new(stamp.id("down"))
+        .label("↓")
+        .style(ButtonStyle::Secondary)
+        .disabled(is_blocked(Step::Down));
+
+    let up = CreateButton::new(stamp.id("up"))
+        .label("↑")
+        .style(ButtonStyle::Secondary)
+        .disabled(is_blocked(Step::Up));
+
+    let right = CreateButton::new(stamp.id("right"))
+        .label("→")
+        .style(ButtonStyle::Secondary)
+        .disabled(is_blocked(Step::Right));
+
+    let label = match cursor_pos {
+        CursorPos::Board(_) => "Select",
+        _ => "Send",
+    };
+
+    let send = CreateButton::new(stamp.id("send"))
+        .label(label)
+        .style(ButtonStyle::Primary)
+        .disabled(!can_send(state, cursor_pos));
 
-    let alpha = pixel2[3] as f32 / 255.0;
-    let mut output = Rgb([0, 0, 0]);
+    let mut action_rows = vec![CreateActionRow::Buttons(vec![left, down, up, right, send])];
 
-    for i in 0..=2 {
-        let pixel_f32 = pixel[i] as f32 / 255.0;
-        let pixel2_f32 = pixel2[i] as f32 / 255.0;
+    if can_go_back(state, cursor_pos) {
+        let back = CreateButton::new(stamp.id("back"))
+            .label("Back to boards")
+            .style(ButtonStyle::Secondary);
 
-        output.0[i] =
-            ((pixel_f32 * (1.0 - alpha) + pixel2_f32 * alpha) * 255.0).clamp(0.0, 255.0) as u8;
+        action_rows.push(CreateActionRow::Buttons(vec![back]));
     }
 
-    canvas.draw_pixel(x, y, output);
+    action_rows
 }
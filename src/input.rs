@@ -0,0 +1,21 @@
+// How the player chooses the cell: moving the cursor with the arrows or
+// pressing the button of the cell on 3x3 boards
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Input {
+    #[default]
+    Cursor,
+    Grid,
+}
+
+impl Input {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cursor" => Some(Input::Cursor),
+            "grid" => Some(Input::Grid),
+            _ => None,
+        }
+    }
+}
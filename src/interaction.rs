@@ -0,0 +1,38 @@
+// Helpers of the interactions shared by the handlers of the commands
+
+use serenity::all::{CommandDataOptionValue, CommandInteraction};
+use serenity::builder::{CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage};
+use serenity::http::Http;
+
+use crate::error::Result;
+
+pub fn option_value<'a>(
+    interaction: &'a CommandInteraction,
+    name: &str,
+) -> Option<&'a CommandDataOptionValue> {
+    interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .map(|option| &option.value)
+}
+
+pub async fn reply_ephemeral(
+    http: &Http,
+    interaction: &CommandInteraction,
+    title: &str,
+) -> Result<()> {
+    interaction
+        .create_response(
+            http,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .ephemeral(true)
+                    .embed(CreateEmbed::new().title(title)),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
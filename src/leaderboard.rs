@@ -0,0 +1,609 @@
+// Leaderboard of a guild: the command, the options of the table, its picture
+// and the pinned messages which are redrawn after the games.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use image::imageops::FilterType;
+use image::{ImageBuffer, Rgb};
+use imageproc::drawing::{draw_filled_rect_mut, draw_text_mut, text_size};
+use imageproc::rect::Rect;
+use rusttype::{Font, Scale};
+use serde::{Deserialize, Serialize};
+use serenity::all::{CommandInteraction, CommandOptionType};
+use serenity::builder::{
+    CreateAttachment, CreateCommand, CreateCommandOption, CreateEmbed, CreateMessage,
+    EditInteractionResponse, EditMessage,
+};
+use serenity::http::Http;
+use serenity::model::prelude::{ChannelId, GuildId, MessageId, UserId};
+use tokio::sync::Mutex;
+
+use crate::error::Result;
+use crate::interaction::{option_value, reply_ephemeral};
+use crate::pool::RenderPool;
+use crate::render::Stage;
+use crate::stats::{self, Ranking, Stats};
+use crate::storage::JsonFile;
+use crate::theme::{Theme, ThemeChoices};
+
+pub const SIZE: usize = 10; // Count of the players in the table
+pub const AVATAR_SIZE: u32 = 40;
+
+const WIDTH: u32 = 600;
+const ROW_HEIGHT: u32 = 56;
+const HEADER_HEIGHT: u32 = 40;
+const PADDING: u32 = 16;
+const FONT_SIZE: f32 = 24.0;
+const HEADER_FONT_SIZE: f32 = 18.0;
+
+const STRIPE: u32 = 8; // Percents of the text color in the background of every second row
+const DIMMED: u32 = 55; // Percents of the text color in the less important text
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Period {
+    Week,
+    Month,
+    #[default]
+    AllTime,
+}
+
+impl Period {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "week" => Some(Period::Week),
+            "month" => Some(Period::Month),
+            "all" => Some(Period::AllTime),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Period::Week => "week",
+            Period::Month => "month",
+            Period::AllTime => "all",
+        }
+    }
+
+    // The oldest counted game, in seconds since the Unix epoch
+    pub fn since(self, now: u64) -> Option<u64> {
+        match self {
+            Period::Week => Some(now.saturating_sub(7 * DAY)),
+            Period::Month => Some(now.saturating_sub(30 * DAY)),
+            Period::AllTime => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pin {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    pub period: Period,
+    pub ranking: Ranking,
+}
+
+#[derive(Default)]
+pub struct Pins {
+    file: JsonFile,
+    pins: Vec<Pin>,
+    outdated: HashSet<GuildId>, // Guilds with the games after the last refresh
+}
+
+impl Pins {
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let file = JsonFile::new(path);
+
+        Self {
+            pins: file.load(),
+            file,
+            outdated: HashSet::new(),
+        }
+    }
+
+    fn save(&mut self) {
+        self.file.save(&self.pins);
+    }
+
+    // One leaderboard per channel, the old one is returned to be unpinned
+    pub fn add(&mut self, pin: Pin) -> Option<Pin> {
+        let old = self.remove(pin.channel_id);
+
+        self.pins.push(pin);
+        self.save();
+
+        old
+    }
+
+    pub fn remove(&mut self, channel_id: ChannelId) -> Option<Pin> {
+        let pos = self
+            .pins
+            .iter()
+            .position(|pin| pin.channel_id == channel_id)?;
+        let pin = self.pins.swap_remove(pos);
+
+        self.save();
+
+        Some(pin)
+    }
+
+    pub fn mark_outdated(&mut self, guild_id: GuildId) {
+        self.outdated.insert(guild_id);
+    }
+
+    // Week and month tables change with time too, without any new games
+    pub fn mark_periods_outdated(&mut self) {
+        let guilds = self
+            .pins
+            .iter()
+            .filter(|pin| pin.period != Period::AllTime)
+            .map(|pin| pin.guild_id);
+
+        self.outdated.extend(guilds);
+    }
+
+    // Pins of the guilds where someone has played since the last call
+    pub fn take_outdated(&mut self) -> Vec<Pin> {
+        let outdated = std::mem::take(&mut self.outdated);
+
+        self.pins
+            .iter()
+            .filter(|pin| outdated.contains(&pin.guild_id))
+            .copied()
+            .collect()
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("leaderboard")
+        .description("Show the best players of the server")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "period", "All time by default")
+                .add_string_choice("Week", Period::Week.name())
+                .add_string_choice("Month", Period::Month.name())
+                .add_string_choice("All time", Period::AllTime.name()),
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "by", "Rating by default")
+                .add_string_choice("Rating", Ranking::Rating.name())
+                .add_string_choice("Wins", Ranking::Wins.name()),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "pin",
+            "Pin the leaderboard to the channel and refresh it after the games (admins only)",
+        ))
+}
+
+// Tables are drawn from the stats of the games, in the theme of the server
+pub struct Leaderboards<'a> {
+    pub pins: &'a Mutex<Pins>,
+    pub stats: &'a Mutex<Stats>,
+    pub theme_choices: &'a Mutex<ThemeChoices>,
+    pub render: &'a RenderPool,
+}
+
+impl Leaderboards<'_> {
+    pub async fn command(&self, http: &Http, interaction: &CommandInteraction) -> Result<()> {
+        let Some(guild_id) = interaction.guild_id else {
+            return reply_ephemeral(
+                http,
+                interaction,
+                "The leaderboard is available only on a server.",
+            )
+            .await;
+        };
+
+        let period = option_value(interaction, "period")
+            .and_then(|value| value.as_str())
+            .and_then(Period::from_name)
+            .unwrap_or_default();
+
+        let ranking = option_value(interaction, "by")
+            .and_then(|value| value.as_str())
+            .and_then(Ranking::from_name)
+            .unwrap_or_default();
+
+        let is_admin = matches!(
+            interaction.member.as_ref().and_then(|member| member.permissions),
+            Some(permissions) if permissions.manage_guild()
+        );
+
+        match option_value(interaction, "pin").and_then(|value| value.as_bool()) {
+            None => {
+                // Avatars are downloaded, so it can take longer than the response time
+                interaction.defer(http).await?;
+
+                let (embed, attachment) = self.generate(http, guild_id, period, ranking).await?;
+
+                interaction
+                    .edit_response(
+                        http,
+                        EditInteractionResponse::new()
+                            .embed(embed)
+                            .new_attachment(attachment),
+                    )
+                    .await?;
+            }
+
+            Some(_) if !is_admin => {
+                reply_ephemeral(
+                    http,
+                    interaction,
+                    "Only the administrators of the server can pin the leaderboard.",
+                )
+                .await?;
+            }
+
+            Some(true) => {
+                interaction.defer_ephemeral(http).await?;
+
+                let (embed, attachment) = self.generate(http, guild_id, period, ranking).await?;
+
+                let message = interaction
+                    .channel_id
+                    .send_message(http, CreateMessage::new().embed(embed).add_file(attachment))
+                    .await?;
+
+                message.pin(http).await?;
+
+                let old = self.pins.lock().await.add(Pin {
+                    guild_id,
+                    channel_id: message.channel_id,
+                    message_id: message.id,
+                    period,
+                    ranking,
+                });
+
+                if let Some(old) = old {
+                    // Message could have been deleted already
+                    let _ = old.channel_id.unpin(http, old.message_id).await;
+                }
+
+                interaction
+                    .edit_response(
+                        http,
+                        EditInteractionResponse::new().embed(CreateEmbed::new().title(
+                            "The leaderboard is pinned, it will be refreshed after the games.",
+                        )),
+                    )
+                    .await?;
+            }
+
+            Some(false) => {
+                let old = self.pins.lock().await.remove(interaction.channel_id);
+
+                let title = match old {
+                    Some(old) => {
+                        let _ = old.channel_id.unpin(http, old.message_id).await;
+                        "The leaderboard is unpinned."
+                    }
+                    None => "There is no pinned leaderboard in this channel.",
+                };
+
+                reply_ephemeral(http, interaction, title).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn generate(
+        &self,
+        http: &Http,
+        guild_id: GuildId,
+        period: Period,
+        ranking: Ranking,
+    ) -> Result<(CreateEmbed, CreateAttachment)> {
+        let standings = {
+            let stats = self.stats.lock().await;
+            stats.leaderboard(guild_id, period.since(stats::now()), ranking)
+        };
+
+        let mut rows = Vec::new();
+
+        for standing in standings.into_iter().take(SIZE) {
+            let score = standing.score;
+
+            let value = match (ranking, period) {
+                (Ranking::Rating, Period::AllTime) => (standing.rating.round() as i32).to_string(),
+                (Ranking::Rating, _) => format!("{:+}", standing.gain.round() as i32),
+                (Ranking::Wins, _) => score.wins.to_string(),
+            };
+
+            rows.push(Row {
+                avatar: fetch_avatar(http, standing.user_id).await,
+                name: standing.name,
+                value,
+                detail: format!("{} / {} / {}", score.wins, score.losses, score.draws),
+            });
+        }
+
+        let (value_title, ranking_text) = match (ranking, period) {
+            (Ranking::Rating, Period::AllTime) => ("Rating", "rating"),
+            (Ranking::Rating, _) => ("Gain", "rating gain"),
+            (Ranking::Wins, _) => ("Wins", "wins"),
+        };
+
+        let period_text = match period {
+            Period::Week => "this week",
+            Period::Month => "this month",
+            Period::AllTime => "all time",
+        };
+
+        let theme = self
+            .theme_choices
+            .lock()
+            .await
+            .guild_theme(guild_id)
+            .to_string();
+
+        let png = self
+            .render
+            .run(move |renderer| {
+                let theme = renderer.themes().get(&theme);
+                let font = renderer.font().expect("font is loaded in Renderer::open");
+
+                let start = Instant::now();
+                let image = render(font, theme, value_title, &rows);
+                renderer.record(Stage::Draw, start.elapsed());
+
+                renderer.encode(&image)
+            })
+            .await?;
+
+        let embed = CreateEmbed::new()
+            .title(format!(
+                "Leaderboard of the server by {} for {}",
+                ranking_text, period_text,
+            ))
+            .attachment("leaderboard.png");
+
+        Ok((embed, CreateAttachment::bytes(png, "leaderboard.png")))
+    }
+
+    // Pinned tables of the guilds with new games are drawn again
+    pub async fn refresh(&self, http: &Http) {
+        let outdated = self.pins.lock().await.take_outdated();
+
+        for pin in outdated {
+            let result = self
+                .generate(http, pin.guild_id, pin.period, pin.ranking)
+                .await;
+
+            // Tried again with the next refresh
+            let (embed, attachment) = match result {
+                Ok(leaderboard) => leaderboard,
+                Err(err) => {
+                    eprintln!("Failed to refresh the leaderboard: {err:?}");
+                    self.pins.lock().await.mark_outdated(pin.guild_id);
+                    continue;
+                }
+            };
+
+            let result = pin
+                .channel_id
+                .edit_message(
+                    http,
+                    pin.message_id,
+                    EditMessage::new().embed(embed).attachment(attachment),
+                )
+                .await;
+
+            // Someone has deleted the message, there is nothing to refresh anymore
+            if result.is_err() {
+                self.pins.lock().await.remove(pin.channel_id);
+            }
+        }
+    }
+}
+
+// Avatar is optional, the table is drawn without it if Discord doesn't answer
+async fn fetch_avatar(http: &Http, user_id: UserId) -> Option<ImageBuffer<Rgb<u8>, Vec<u8>>> {
+    let user = user_id.to_user(http).await.ok()?;
+    let bytes = reqwest::get(user.static_face())
+        .await
+        .ok()?
+        .bytes()
+        .await
+        .ok()?;
+
+    let avatar = image::load_from_memory(&bytes).ok()?;
+    let size = AVATAR_SIZE;
+
+    Some(
+        avatar
+            .resize_exact(size, size, FilterType::Triangle)
+            .into_rgb8(),
+    )
+}
+
+pub struct Row {
+    pub avatar: Option<ImageBuffer<Rgb<u8>, Vec<u8>>>,
+    pub name: String,
+    pub value: String,  // Rating or count of wins, whatever the table is sorted by
+    pub detail: String, // Wins, losses and draws
+}
+
+// Colors are the ones of the boards in the theme of the server
+pub fn render(
+    font: &Font,
+    theme: &Theme,
+    value_title: &str,
+    rows: &[Row],
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let height = HEADER_HEIGHT + ROW_HEIGHT * rows.len().max(1) as u32;
+    let mut canvas = ImageBuffer::from_pixel(WIDTH, height, theme.background);
+    let (stripe, dimmed) = (shade(theme, STRIPE), shade(theme, DIMMED));
+
+    let header = Scale::uniform(HEADER_FONT_SIZE);
+    let scale = Scale::uniform(FONT_SIZE);
+
+    // Columns: rank, avatar and name on the left, value and details on the right
+    let name_left = PADDING + 40 + AVATAR_SIZE + PADDING;
+    let value_right = WIDTH - PADDING - 150;
+    let detail_right = WIDTH - PADDING;
+
+    let header_top = ((HEADER_HEIGHT as f32 - HEADER_FONT_SIZE) / 2.0) as i32;
+    draw_text_mut(
+        &mut canvas,
+        dimmed,
+        PADDING as i32,
+        header_top,
+        header,
+        font,
+        "#",
+    );
+    draw_text_mut(
+        &mut canvas,
+        dimmed,
+        name_left as i32,
+        header_top,
+        header,
+        font,
+        "Player",
+    );
+    draw_right_aligned(
+        &mut canvas,
+        dimmed,
+        value_right,
+        header_top,
+        header,
+        font,
+        value_title,
+    );
+    draw_right_aligned(
+        &mut canvas,
+        dimmed,
+        detail_right,
+        header_top,
+        header,
+        font,
+        "W / L / D",
+    );
+
+    if rows.is_empty() {
+        let top = (HEADER_HEIGHT + (ROW_HEIGHT - FONT_SIZE as u32) / 2) as i32;
+        let text = "No games have been played yet.";
+
+        draw_text_mut(
+            &mut canvas,
+            theme.grid,
+            PADDING as i32,
+            top,
+            scale,
+            font,
+            text,
+        );
+    }
+
+    for (index, row) in rows.iter().enumerate() {
+        let top = HEADER_HEIGHT + ROW_HEIGHT * index as u32;
+
+        if index % 2 == 0 {
+            draw_filled_rect_mut(
+                &mut canvas,
+                Rect::at(0, top as i32).of_size(WIDTH, ROW_HEIGHT),
+                stripe,
+            );
+        }
+
+        let text_top = (top + (ROW_HEIGHT - FONT_SIZE as u32) / 2) as i32;
+        let rank = (index + 1).to_string();
+
+        draw_text_mut(
+            &mut canvas,
+            theme.grid,
+            PADDING as i32,
+            text_top,
+            scale,
+            font,
+            &rank,
+        );
+
+        if let Some(avatar) = &row.avatar {
+            let left = PADDING + 40;
+            let avatar_top = top + (ROW_HEIGHT - AVATAR_SIZE) / 2;
+
+            for (x, y, pixel) in avatar.enumerate_pixels() {
+                canvas.put_pixel(left + x, avatar_top + y, *pixel);
+            }
+        }
+
+        let name = fit_text(font, scale, &row.name, value_right - name_left - 80);
+        draw_text_mut(
+            &mut canvas,
+            theme.grid,
+            name_left as i32,
+            text_top,
+            scale,
+            font,
+            &name,
+        );
+
+        draw_right_aligned(
+            &mut canvas,
+            theme.grid,
+            value_right,
+            text_top,
+            scale,
+            font,
+            &row.value,
+        );
+        draw_right_aligned(
+            &mut canvas,
+            dimmed,
+            detail_right,
+            text_top,
+            scale,
+            font,
+            &row.detail,
+        );
+    }
+
+    canvas
+}
+
+fn draw_right_aligned(
+    canvas: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+    color: Rgb<u8>,
+    right: u32,
+    top: i32,
+    scale: Scale,
+    font: &Font,
+    text: &str,
+) {
+    let (width, _) = text_size(scale, font, text);
+
+    draw_text_mut(canvas, color, right as i32 - width, top, scale, font, text);
+}
+
+// Long names are cut, so they don't run into the next column
+fn fit_text(font: &Font, scale: Scale, text: &str, width: u32) -> String {
+    if text_size(scale, font, text).0 <= width as i32 {
+        return text.to_string();
+    }
+
+    let mut fitted: String = text.to_string();
+
+    while !fitted.is_empty() && text_size(scale, font, &format!("{}…", fitted)).0 > width as i32 {
+        fitted.pop();
+    }
+
+    format!("{}…", fitted)
+}
+
+// Text color mixed into the background
+fn shade(theme: &Theme, percents: u32) -> Rgb<u8> {
+    let (Rgb(background), Rgb(text)) = (theme.background, theme.grid);
+
+    Rgb([0, 1, 2].map(|channel| {
+        let (background, text) = (background[channel] as u32, text[channel] as u32);
+        ((background * (100 - percents) + text * percents) / 100) as u8
+    }))
+}
@@ -1,5 +1,25 @@
+mod ai;
+mod clock;
+mod draw;
+mod engine;
+mod error;
 mod game;
+mod input;
+mod interaction;
+mod leaderboard;
+mod notation;
 mod ping;
+mod pool;
+mod queue;
+mod rating;
+mod render;
+mod restore;
+mod sessions;
+mod stats;
+mod storage;
+mod theme;
+
+use std::sync::Arc;
 
 use serenity::all::Interaction;
 use serenity::all::Ready;
@@ -10,12 +30,14 @@ use serenity::prelude::*;
 use game::Game;
 
 struct Handler {
-    game: Game,
+    game: Arc<Game>,
 }
 
 impl Handler {
     fn new() -> Self {
-        Self { game: Game::new() }
+        Self {
+            game: Arc::new(Game::new()),
+        }
     }
 }
 
@@ -25,9 +47,10 @@ impl EventHandler for Handler {
         match interaction {
             Interaction::Command(command) => match command.data.name.as_str() {
                 "ping" => ping::command(ctx, command).await,
-                "play" => self.game.command(ctx, command).await,
+                "play" | "stop" | "stats" | "leaderboard" | "export" | "replay" | "queue"
+                | "theme" => self.game.command(ctx, command).await,
                 _ => {
-                    command
+                    let result = command
                         .create_response(
                             &ctx.http,
                             CreateInteractionResponse::Message(
@@ -36,8 +59,11 @@ impl EventHandler for Handler {
                                     .content("Invalid command!"),
                             ),
                         )
-                        .await
-                        .expect("failed to create response");
+                        .await;
+
+                    if let Err(err) = result {
+                        eprintln!("Failed to answer the command: {err:?}");
+                    }
                 }
             },
 
@@ -62,11 +88,19 @@ impl EventHandler for Handler {
                 vec![
                     Game::register_play(),
                     Game::register_stop(),
+                    Game::register_stats(),
+                    leaderboard::register(),
+                    Game::register_export(),
+                    Game::register_replay(),
+                    Game::register_queue(),
+                    self.game.register_theme(),
                     ping::register(),
                 ],
             )
             .await
             .expect("failed to create application command");
+
+        self.game.start_background_tasks(ctx.http.clone());
     }
 }
 
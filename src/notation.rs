@@ -0,0 +1,249 @@
+// Text notation of the games, similar to PGN of chess. Columns are letters
+// from the left, rows are numbers from the top, so the center of the classic
+// board is b2. The ultimate board is written as one 9x9 board.
+//
+// [Game "12"]
+// [Date "2023.01.18"]
+// [X "Vadim"]
+// [O "Danil"]
+// [Variant "Classic"]
+// [Size "3"]
+// [Win "3"]
+// [TimeControl "180+2"]
+// [Result "1-0"]
+// [Termination "Normal"]
+// [Position "xxx/oo1/3 o"]
+//
+// 1. b2 {1.2s} a1 {3.0s} 2. a2 {0.8s} ...
+
+use crate::engine::{ultimate, Cell, Mode, Player, Variant};
+use crate::stats::{Ending, GameRecord};
+
+pub fn square(mode: Mode, index: usize) -> String {
+    let (row, column) = match mode {
+        Mode::Classic(rules) => (index / rules.size, index % rules.size),
+
+        Mode::Ultimate => {
+            let (board, cell) = ultimate::split_index(index);
+            (board / 3 * 3 + cell / 3, board % 3 * 3 + cell % 3)
+        }
+    };
+
+    format!("{}{}", (b'a' + column as u8) as char, row + 1)
+}
+
+// Rows from the top separated with slashes, digits are counts of empty
+// cells, and the player on turn at the end
+pub fn position(state: &Variant) -> String {
+    let (size, cells): (usize, Vec<Cell>) = match state {
+        Variant::Classic(state) => {
+            let board = state.board();
+            (
+                board.size(),
+                (0..board.cell_count())
+                    .map(|cell| board.cell(cell))
+                    .collect(),
+            )
+        }
+
+        Variant::Ultimate(state) => {
+            let cells = (0..81)
+                .map(|cell| {
+                    let (row, column) = (cell / 9, cell % 9);
+                    let board = row / 3 * 3 + column / 3;
+
+                    state.board(board).cell(row % 3 * 3 + column % 3)
+                })
+                .collect();
+
+            (9, cells)
+        }
+    };
+
+    let rows: Vec<String> = cells
+        .chunks(size)
+        .map(|row| {
+            let mut text = String::new();
+            let mut empty = 0;
+
+            for cell in row {
+                let piece = match cell {
+                    Cell::Empty => {
+                        empty += 1;
+                        continue;
+                    }
+                    Cell::Taken(player) => piece(*player),
+                };
+
+                if empty > 0 {
+                    text.push_str(&empty.to_string());
+                    empty = 0;
+                }
+
+                text.push(piece);
+            }
+
+            if empty > 0 {
+                text.push_str(&empty.to_string());
+            }
+
+            text
+        })
+        .collect();
+
+    format!("{} {}", rows.join("/"), piece(state.turn()))
+}
+
+pub fn export(record: &GameRecord) -> String {
+    let mut state = Variant::new(record.mode);
+
+    for index in &record.moves {
+        // Saved games are always legal, so the error is just skipped
+        let _ = state.play(*index);
+    }
+
+    let mut headers = vec![
+        ("Game", record.id.to_string()),
+        ("Date", date(record.timestamp)),
+        ("X", record.player.1.clone()),
+        ("O", record.player2.1.clone()),
+    ];
+
+    match record.mode {
+        Mode::Classic(rules) => {
+            headers.push(("Variant", "Classic".to_string()));
+            headers.push(("Size", rules.size.to_string()));
+            headers.push(("Win", rules.win.to_string()));
+        }
+        Mode::Ultimate => headers.push(("Variant", "Ultimate".to_string())),
+    }
+
+    let result = match record.winner {
+        Some(Player::First) => "1-0",
+        Some(Player::Second) => "0-1",
+        None => "1/2-1/2",
+    };
+
+    let termination = match record.ending {
+        Ending::Board => "Normal",
+        Ending::Resignation => "Resignation",
+        Ending::Time => "Time forfeit",
+        Ending::Agreement => "Agreement",
+    };
+
+    // Seconds and the increment like in PGN, e.g. 180+2
+    if let Some(control) = record.time_control {
+        headers.push((
+            "TimeControl",
+            format!("{}+{}", control.base.as_secs(), control.increment.as_secs()),
+        ));
+    }
+
+    headers.push(("Result", result.to_string()));
+    headers.push(("Termination", termination.to_string()));
+    headers.push(("Position", position(&state)));
+
+    let mut text: String = headers
+        .iter()
+        .map(|(name, value)| format!("[{} \"{}\"]\n", name, value.replace('"', "'")))
+        .collect();
+
+    text.push('\n');
+
+    // Times are the seconds spent on the move, the old records don't have them
+    let mut last_time = 0;
+
+    for (number, index) in record.moves.iter().enumerate() {
+        if number % 2 == 0 {
+            text.push_str(&format!("{}. ", number / 2 + 1));
+        }
+
+        text.push_str(&square(record.mode, *index));
+
+        if let Some(time) = record.times.get(number) {
+            text.push_str(&format!(" {{{:.1}s}}", (time - last_time) as f64 / 1000.0));
+            last_time = *time;
+        }
+
+        text.push(' ');
+    }
+
+    text.push_str(result);
+    text.push('\n');
+
+    text
+}
+
+fn piece(player: Player) -> char {
+    match player {
+        Player::First => 'x',
+        Player::Second => 'o',
+    }
+}
+
+// Date of the Unix time in the PGN format, e.g. 2023.01.18
+fn date(timestamp: u64) -> String {
+    // Days to the civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Rules;
+
+    #[test]
+    fn dates_of_unix_times() {
+        assert_eq!(date(0), "1970.01.01");
+        assert_eq!(date(951_782_400), "2000.02.29");
+        assert_eq!(date(1_709_164_800 + 86_399), "2024.02.29");
+        assert_eq!(date(1_709_251_200), "2024.03.01");
+        assert_eq!(date(1_704_067_199), "2023.12.31");
+    }
+
+    #[test]
+    fn squares_of_the_boards() {
+        assert_eq!(square(Mode::default(), 0), "a1");
+        assert_eq!(square(Mode::default(), 4), "b2");
+
+        let rules = Rules::new(15, 5).unwrap();
+        assert_eq!(square(Mode::Classic(rules), 224), "o15");
+
+        // Board by board: the center of the center board, the bottom left
+        // cell of the top right board and the last cell of the last board
+        assert_eq!(square(Mode::Ultimate, 40), "e5");
+        assert_eq!(square(Mode::Ultimate, 24), "g3");
+        assert_eq!(square(Mode::Ultimate, 80), "i9");
+    }
+
+    #[test]
+    fn positions_count_the_empty_cells() {
+        let mut state = Variant::new(Mode::default());
+        assert_eq!(position(&state), "3/3/3 x");
+
+        for index in [0, 4, 8] {
+            state.play(index).unwrap();
+        }
+        assert_eq!(position(&state), "x2/1o1/2x o");
+
+        let mut state = Variant::new(Mode::Ultimate);
+        state.play(8).unwrap(); // Bottom right cell of the top left board
+        assert_eq!(position(&state), "9/9/2x6/9/9/9/9/9/9 o");
+    }
+}
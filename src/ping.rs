@@ -8,7 +8,7 @@ pub fn register() -> CreateCommand {
 }
 
 pub async fn command(ctx: Context, interaction: CommandInteraction) {
-    interaction
+    let result = interaction
         .create_response(
             &ctx.http,
             CreateInteractionResponse::Message(
@@ -17,6 +17,9 @@ pub async fn command(ctx: Context, interaction: CommandInteraction) {
                     .content("pong!"),
             ),
         )
-        .await
-        .expect("failed to create interaction");
+        .await;
+
+    if let Err(err) = result {
+        eprintln!("Failed to answer the ping: {err:?}");
+    }
 }
@@ -0,0 +1,91 @@
+// Pictures are drawn and encoded on the threads for the blocking work, so the
+// handlers of the events don't stall the gateway. Only a few jobs run at once,
+// a few more wait for them, and the rest are turned away until the load drops.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use tokio::sync::Semaphore;
+
+use crate::error::{Error, Result};
+use crate::render::{Metrics, Renderer, Scene, Stage};
+use crate::theme::Themes;
+
+const QUEUE_SIZE: usize = 64; // Jobs waiting for a worker
+
+pub struct RenderPool {
+    renderer: Arc<Renderer>,
+    workers: Arc<Semaphore>, // Permits of the running jobs
+    slots: Arc<Semaphore>,   // Permits of the running and the waiting jobs
+    size: usize,
+}
+
+impl RenderPool {
+    pub fn new(renderer: Renderer) -> Self {
+        let workers = thread::available_parallelism().map_or(2, NonZeroUsize::get);
+        let size = workers + QUEUE_SIZE;
+
+        Self {
+            renderer: Arc::new(renderer),
+            workers: Arc::new(Semaphore::new(workers)),
+            slots: Arc::new(Semaphore::new(size)),
+            size,
+        }
+    }
+
+    pub async fn run<T, F>(&self, job: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Renderer) -> T + Send + 'static,
+    {
+        let Ok(slot) = Arc::clone(&self.slots).try_acquire_owned() else {
+            self.renderer.record_rejection();
+            return Err(Error::Busy);
+        };
+
+        self.renderer
+            .record_load(self.size - self.slots.available_permits());
+
+        let start = Instant::now();
+        let worker = Arc::clone(&self.workers)
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        self.renderer.record(Stage::Wait, start.elapsed());
+
+        let renderer = Arc::clone(&self.renderer);
+
+        // Permits go away with the job, even if the handler has stopped waiting for it
+        let result = tokio::task::spawn_blocking(move || {
+            let _permits = (slot, worker);
+            job(&renderer)
+        })
+        .await;
+
+        // Panic of the drawing stays in its thread, the player sees an error
+        result.map_err(|err| {
+            eprintln!("Failed to draw the picture: {err:?}");
+            Error::RenderFailed
+        })
+    }
+
+    pub async fn png(&self, scene: Scene) -> Result<Arc<Vec<u8>>> {
+        self.run(move |renderer| renderer.png(&scene)).await
+    }
+
+    pub fn themes(&self) -> &Themes {
+        self.renderer.themes()
+    }
+
+    pub fn take_metrics(&self) -> Metrics {
+        self.renderer.take_metrics()
+    }
+}
+
+impl Default for RenderPool {
+    fn default() -> Self {
+        Self::new(Renderer::default())
+    }
+}
@@ -0,0 +1,257 @@
+// Matchmaking queues. Every guild chooses where its players wait for an
+// opponent, a player can also opt in to the queue shared by all guilds.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::{ChannelId, GuildId};
+
+use crate::storage::JsonFile;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Policy {
+    #[default]
+    Fifo, // First come, first served within the whole guild
+    SameChannel,
+    CrossServer, // Players of every guild which opted in share the queue
+}
+
+impl Policy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fifo" => Some(Policy::Fifo),
+            "channel" => Some(Policy::SameChannel),
+            "global" => Some(Policy::CrossServer),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Policy::Fifo => "fifo",
+            Policy::SameChannel => "channel",
+            Policy::CrossServer => "global",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QueueKey {
+    Channel(ChannelId),
+    Guild(GuildId),
+    Global,
+}
+
+impl QueueKey {
+    // Direct messages have no guild, so the channel is the only place to wait in
+    pub fn new(policy: Policy, guild_id: Option<GuildId>, channel_id: ChannelId) -> Self {
+        match (policy, guild_id) {
+            (Policy::CrossServer, _) => QueueKey::Global,
+            (Policy::Fifo, Some(guild_id)) => QueueKey::Guild(guild_id),
+            _ => QueueKey::Channel(channel_id),
+        }
+    }
+}
+
+// Waiting players are lost with a restart, only the policies of the guilds
+// are saved
+pub struct Queues<T> {
+    queues: HashMap<QueueKey, Vec<T>>, // The longest waiting player goes first
+    policies: HashMap<GuildId, Policy>,
+    file: JsonFile,
+}
+
+impl<T> Default for Queues<T> {
+    fn default() -> Self {
+        Self {
+            queues: HashMap::new(),
+            policies: HashMap::new(),
+            file: JsonFile::default(),
+        }
+    }
+}
+
+impl<T> Queues<T> {
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let file = JsonFile::new(path);
+
+        Self {
+            queues: HashMap::new(),
+            policies: file.load(),
+            file,
+        }
+    }
+
+    fn save(&mut self) {
+        self.file.save(&self.policies);
+    }
+
+    pub fn policy(&self, guild_id: Option<GuildId>) -> Policy {
+        guild_id
+            .and_then(|guild_id| self.policies.get(&guild_id).copied())
+            .unwrap_or_default()
+    }
+
+    pub fn set_policy(&mut self, guild_id: GuildId, policy: Policy) {
+        self.policies.insert(guild_id, policy);
+        self.save();
+    }
+
+    pub fn push(&mut self, key: QueueKey, player: T) {
+        self.queues.entry(key).or_default().push(player);
+    }
+
+    // Takes the longest waiting player of the queue who fits the predicate
+    pub fn take(&mut self, key: QueueKey, predicate: impl Fn(&T) -> bool) -> Option<T> {
+        let queue = self.queues.get_mut(&key)?;
+        let pos = queue.iter().position(predicate)?;
+        let player = queue.remove(pos);
+
+        if queue.is_empty() {
+            self.queues.remove(&key);
+        }
+
+        Some(player)
+    }
+
+    // Looks through all of the queues, a player can wait only in one of them
+    pub fn remove(&mut self, predicate: impl Fn(&T) -> bool) -> Option<T> {
+        let key = *self
+            .queues
+            .iter()
+            .find(|(_, queue)| queue.iter().any(&predicate))?
+            .0;
+
+        self.take(key, predicate)
+    }
+
+    // Pair of the players of one queue, the longer waiting one goes first
+    pub fn take_pair(&mut self, predicate: impl Fn(&T, &T) -> bool) -> Option<(T, T)> {
+        let (key, first, second) = self.queues.iter().find_map(|(key, queue)| {
+            (0..queue.len()).find_map(|first| {
+                (first + 1..queue.len())
+                    .find(|second| predicate(&queue[first], &queue[*second]))
+                    .map(|second| (*key, first, second))
+            })
+        })?;
+
+        let queue = self.queues.get_mut(&key)?;
+        let player2 = queue.remove(second);
+        let player = queue.remove(first);
+
+        if queue.is_empty() {
+            self.queues.remove(&key);
+        }
+
+        Some((player, player2))
+    }
+
+    pub fn contains(&self, predicate: impl Fn(&T) -> bool) -> bool {
+        self.queues.values().flatten().any(predicate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const GUILD2: GuildId = GuildId::new(2);
+    const CHANNEL: ChannelId = ChannelId::new(10);
+    const CHANNEL2: ChannelId = ChannelId::new(20);
+
+    // Players are numbers, the smaller one has waited longer
+    fn queue_of(policy: Policy, places: &[(Option<GuildId>, ChannelId)]) -> Queues<usize> {
+        let mut queues = Queues::default();
+
+        for (player, (guild_id, channel_id)) in places.iter().enumerate() {
+            queues.push(QueueKey::new(policy, *guild_id, *channel_id), player);
+        }
+
+        queues
+    }
+
+    #[test]
+    fn fifo_pairs_the_whole_guild() {
+        let mut queues = queue_of(Policy::Fifo, &[(Some(GUILD), CHANNEL)]);
+        let key = QueueKey::new(Policy::Fifo, Some(GUILD), CHANNEL2);
+
+        assert_eq!(queues.take(key, |_| true), Some(0));
+        assert_eq!(queues.take(key, |_| true), None);
+    }
+
+    #[test]
+    fn same_channel_pairs_only_the_channel() {
+        let mut queues = queue_of(Policy::SameChannel, &[(Some(GUILD), CHANNEL)]);
+
+        let key = QueueKey::new(Policy::SameChannel, Some(GUILD), CHANNEL2);
+        assert_eq!(queues.take(key, |_| true), None);
+
+        let key = QueueKey::new(Policy::SameChannel, Some(GUILD), CHANNEL);
+        assert_eq!(queues.take(key, |_| true), Some(0));
+    }
+
+    #[test]
+    fn cross_server_pairs_every_guild() {
+        let mut queues = queue_of(Policy::CrossServer, &[(Some(GUILD), CHANNEL)]);
+        let key = QueueKey::new(Policy::CrossServer, Some(GUILD2), CHANNEL2);
+
+        assert_eq!(queues.take(key, |_| true), Some(0));
+    }
+
+    #[test]
+    fn direct_messages_wait_in_the_channel() {
+        assert_eq!(
+            QueueKey::new(Policy::Fifo, None, CHANNEL),
+            QueueKey::Channel(CHANNEL)
+        );
+    }
+
+    #[test]
+    fn take_gives_the_longest_waiting_fitting_player() {
+        let place = (Some(GUILD), CHANNEL);
+        let mut queues = queue_of(Policy::Fifo, &[place, place, place]);
+        let key = QueueKey::new(Policy::Fifo, Some(GUILD), CHANNEL);
+
+        assert_eq!(queues.take(key, |player| *player > 0), Some(1));
+        assert_eq!(queues.take(key, |_| true), Some(0));
+        assert_eq!(queues.take(key, |player| *player != 2), None);
+    }
+
+    #[test]
+    fn take_pair_stays_within_a_queue() {
+        for policy in [Policy::Fifo, Policy::SameChannel] {
+            let mut queues = queue_of(policy, &[(Some(GUILD), CHANNEL), (Some(GUILD2), CHANNEL2)]);
+            assert_eq!(queues.take_pair(|_, _| true), None);
+        }
+
+        let places = [(Some(GUILD), CHANNEL), (Some(GUILD2), CHANNEL2)];
+        let mut queues = queue_of(Policy::CrossServer, &places);
+        assert_eq!(queues.take_pair(|_, _| true), Some((0, 1)));
+        assert!(!queues.contains(|_| true));
+    }
+
+    #[test]
+    fn take_pair_skips_the_players_who_do_not_fit() {
+        let place = (Some(GUILD), CHANNEL);
+        let mut queues = queue_of(Policy::Fifo, &[place, place, place]);
+
+        assert_eq!(
+            queues.take_pair(|player, player2| player + player2 == 2),
+            Some((0, 2))
+        );
+        assert!(queues.contains(|player| *player == 1));
+    }
+
+    #[test]
+    fn policy_is_fifo_until_chosen() {
+        let mut queues: Queues<usize> = Queues::default();
+        assert_eq!(queues.policy(Some(GUILD)), Policy::Fifo);
+
+        queues.set_policy(GUILD, Policy::CrossServer);
+        assert_eq!(queues.policy(Some(GUILD)), Policy::CrossServer);
+        assert_eq!(queues.policy(Some(GUILD2)), Policy::Fifo);
+        assert_eq!(queues.policy(None), Policy::Fifo);
+    }
+}
@@ -0,0 +1,124 @@
+// Elo ratings of the players. Ratings aren't stored anywhere, the saved
+// results are replayed on start instead.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serenity::model::prelude::UserId;
+
+pub const INITIAL_RATING: f64 = 1500.0;
+const K_FACTOR: f64 = 32.0;
+
+// Players wait for an opponent with the close rating first, but the window
+// grows, so no one waits forever
+const BASE_WINDOW: f64 = 100.0;
+const WINDOW_GROWTH: f64 = 10.0; // Per second of waiting
+
+#[derive(Clone, Copy, Debug)]
+pub struct Change {
+    pub old: f64,
+    pub new: f64,
+}
+
+impl Change {
+    pub fn rating(&self) -> i32 {
+        self.new.round() as i32
+    }
+
+    // Difference of the shown ratings, so it always adds up
+    pub fn delta(&self) -> i32 {
+        self.rating() - self.old.round() as i32
+    }
+}
+
+#[derive(Default)]
+pub struct Ratings {
+    ratings: HashMap<UserId, f64>,
+}
+
+impl Ratings {
+    pub fn get(&self, user_id: UserId) -> f64 {
+        self.ratings
+            .get(&user_id)
+            .copied()
+            .unwrap_or(INITIAL_RATING)
+    }
+
+    // Score is 1 for the win of the first player, 0.5 for a draw, 0 for a loss
+    pub fn update(&mut self, player: UserId, player2: UserId, score: f64) -> (Change, Change) {
+        let (rating, rating2) = (self.get(player), self.get(player2));
+
+        let expected = 1.0 / (1.0 + 10_f64.powf((rating2 - rating) / 400.0));
+        let delta = K_FACTOR * (score - expected);
+
+        self.ratings.insert(player, rating + delta);
+        self.ratings.insert(player2, rating2 - delta);
+
+        (
+            Change {
+                old: rating,
+                new: rating + delta,
+            },
+            Change {
+                old: rating2,
+                new: rating2 - delta,
+            },
+        )
+    }
+}
+
+pub fn in_window(waiting_rating: f64, rating: f64, waited: Duration) -> bool {
+    let window = BASE_WINDOW + WINDOW_GROWTH * waited.as_secs_f64();
+
+    (waiting_rating - rating).abs() <= window
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYER: UserId = UserId::new(1);
+    const PLAYER2: UserId = UserId::new(2);
+
+    #[test]
+    fn win_between_equals_moves_half_of_the_factor() {
+        let mut ratings = Ratings::default();
+        let (change, change2) = ratings.update(PLAYER, PLAYER2, 1.0);
+
+        assert_eq!(change.delta(), 16);
+        assert_eq!(change2.delta(), -16);
+        assert_eq!(ratings.get(PLAYER), INITIAL_RATING + 16.0);
+        assert_eq!(ratings.get(PLAYER2), INITIAL_RATING - 16.0);
+    }
+
+    #[test]
+    fn draw_between_equals_changes_nothing() {
+        let mut ratings = Ratings::default();
+        let (change, change2) = ratings.update(PLAYER, PLAYER2, 0.5);
+
+        assert_eq!((change.delta(), change2.delta()), (0, 0));
+    }
+
+    #[test]
+    fn underdog_gains_more_and_points_are_kept() {
+        let mut ratings = Ratings::default();
+        ratings.update(PLAYER, PLAYER2, 1.0);
+
+        let (change, change2) = ratings.update(PLAYER, PLAYER2, 0.0);
+
+        assert!(change2.new - change2.old > 16.0);
+        assert_eq!(change.new - change.old, -(change2.new - change2.old));
+        assert_eq!(
+            ratings.get(PLAYER) + ratings.get(PLAYER2),
+            2.0 * INITIAL_RATING
+        );
+    }
+
+    #[test]
+    fn window_grows_while_waiting() {
+        assert!(in_window(1500.0, 1600.0, Duration::ZERO));
+        assert!(!in_window(1500.0, 1650.0, Duration::ZERO));
+        assert!(!in_window(1650.0, 1500.0, Duration::from_secs(4)));
+        assert!(in_window(1650.0, 1500.0, Duration::from_secs(5)));
+    }
+}
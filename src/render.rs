@@ -0,0 +1,828 @@
+// Pictures of the boards. They are drawn from the state of the game when
+// they are shown, the games keep no pictures. Encoded pictures are cached,
+// the same positions come up in many games and on every cursor move.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::Cursor;
+use std::mem;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::{self, FilterType};
+use image::{Delay, DynamicImage, Frame, ImageBuffer, ImageOutputFormat, Rgb, Rgba};
+use imageproc::drawing::draw_text_mut;
+use rusttype::{point, Font, Rect, Scale};
+
+use crate::draw;
+use crate::engine::ultimate;
+use crate::engine::{Cell, CursorPos, Line, Mode, Outcome, Player, Variant};
+use crate::theme::{Piece, Shape, Strike, StrikeStyle, Theme, Themes, DEFAULT_THEME};
+
+pub type RgbImage = ImageBuffer<Rgb<u8>, Vec<u8>>;
+type RgbaImage = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+const STRIKE: Rgb<u8> = Rgb([20, 130, 255]); // Lines of the themes without their own color
+
+// Sizes are given for the scale 1, the pictures for the HiDPI screens multiply them
+const CELL_SIZE: u32 = 100; // Size of a cell of the classic board
+const MAX_CANVAS_SIZE: u32 = 600; // Bigger boards get smaller cells
+
+// Ultimate board is drawn as a 3x3 grid of places with a small board inside of each
+const ULTIMATE_PLACE_SIZE: u32 = 200;
+const ULTIMATE_MARGIN: u32 = 10;
+const ULTIMATE_LOCAL_CELL_SIZE: u32 = 60;
+
+// Letters of the columns and numbers of the rows, on the left and the top
+const LABEL_MARGIN: u32 = 30;
+const LABEL_SIZE: f32 = 20.0;
+
+const LAST_MOVE_ALPHA: u8 = 56; // Cell of the last move is tinted slightly
+
+const PNG_CACHE_SIZE: usize = 512; // Pictures, a few kilobytes each
+
+const REPLAY_FRAME_DELAY: u32 = 700; // Milliseconds
+const REPLAY_LAST_FRAME_DELAY: u32 = 3000;
+const REPLAY_GIF_SPEED: i32 = 10; // Quality of the colors against the time of encoding, from 1 to 30
+
+// Everything the picture of the board depends on
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Scene {
+    mode: Mode,
+    cells: Vec<Cell>, // Local boards of the ultimate game go one after another
+    local_lines: Vec<(usize, Line)>, // Won local boards of the ultimate game
+    outline: Option<CursorPos>,
+    win_line: Option<Line>,
+    last_move: Option<usize>, // Index of the cell like in the cells
+    labels: bool,
+    theme: String,
+    scale: u32,
+}
+
+impl Scene {
+    pub fn new(state: &Variant, theme: &str) -> Self {
+        let (cells, local_lines) = match state {
+            Variant::Classic(state) => {
+                let board = state.board();
+
+                (
+                    (0..board.cell_count())
+                        .map(|cell| board.cell(cell))
+                        .collect(),
+                    Vec::new(),
+                )
+            }
+
+            Variant::Ultimate(state) => {
+                let cells = (0..ultimate::BOARD_COUNT)
+                    .flat_map(|board| {
+                        (0..ultimate::LOCAL_CELL_COUNT)
+                            .map(move |cell| state.board(board).cell(cell))
+                    })
+                    .collect();
+
+                let local_lines = (0..ultimate::BOARD_COUNT)
+                    .filter_map(|board| match state.local_outcome(board) {
+                        Outcome::Win(_, line) => Some((board, line)),
+                        _ => None,
+                    })
+                    .collect();
+
+                (cells, local_lines)
+            }
+        };
+
+        Self {
+            mode: state.mode(),
+            cells,
+            local_lines,
+            outline: None,
+            win_line: None,
+            last_move: None,
+            labels: false,
+            theme: theme.to_string(),
+            scale: 1,
+        }
+    }
+
+    pub fn outline(mut self, cursor_pos: CursorPos) -> Self {
+        self.outline = Some(cursor_pos);
+        self
+    }
+
+    pub fn win_line(mut self, line: Line) -> Self {
+        self.win_line = Some(line);
+        self
+    }
+
+    pub fn last_move(mut self, index: usize) -> Self {
+        self.last_move = Some(index);
+        self
+    }
+
+    pub fn labels(mut self, labels: bool) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn scale(mut self, scale: u32) -> Self {
+        self.scale = scale;
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct Renderer {
+    themes: Themes,
+    font: Option<Font<'static>>, // Of the labels, the boards have none without it
+    sprites: HashMap<PathBuf, RgbaImage>, // Pieces of every theme
+    sprites_scaled: Mutex<HashMap<(PathBuf, u32), Arc<RgbaImage>>>, // Of the sizes of the cells
+
+    pngs: Mutex<PngCache>,
+    metrics: Mutex<Metrics>,
+}
+
+// The oldest picture goes away first
+#[derive(Default)]
+struct PngCache {
+    pngs: HashMap<Scene, Arc<Vec<u8>>>,
+    order: VecDeque<Scene>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Stage {
+    Wait, // For a free worker of the pool
+    Draw,
+    Encode,
+}
+
+// Latencies of the rendering since the last report
+#[derive(Default)]
+pub struct Metrics {
+    wait: Latency,
+    draw: Latency,
+    encode: Latency,
+    rejected: u32, // Jobs turned away by the full queue of the pool
+    peak: usize,   // Most jobs running and waiting at once
+}
+
+#[derive(Default)]
+struct Latency {
+    count: u32,
+    total: Duration,
+    max: Duration,
+}
+
+impl Renderer {
+    pub fn open() -> Self {
+        let mut themes = Themes::open("./themes");
+        let mut sprites = HashMap::new();
+
+        // Themes with missing sprites aren't offered
+        themes.retain(|id, theme| {
+            for piece in [&theme.x, &theme.o] {
+                let Piece::Sprite { sprite } = piece else {
+                    continue;
+                };
+
+                if sprites.contains_key(sprite) {
+                    continue;
+                }
+
+                match image::open(sprite) {
+                    Ok(image) => {
+                        sprites.insert(sprite.clone(), image.into_rgba8());
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to load the theme {}: {}", id, err);
+                        return false;
+                    }
+                }
+            }
+
+            true
+        });
+
+        assert!(
+            themes.contains(DEFAULT_THEME),
+            "sprites of the default theme are missing"
+        );
+
+        let font = std::fs::read("./resources/DejaVuSans.ttf").expect("DejaVuSans.ttf");
+        let font = Font::try_from_vec(font).expect("DejaVuSans.ttf");
+
+        Self {
+            themes,
+            font: Some(font),
+            sprites,
+
+            ..Default::default()
+        }
+    }
+
+    // Encoded picture, drawn only if it isn't in the cache. It's shared with
+    // the cache, the bytes are copied only for the attachment.
+    pub fn png(&self, scene: &Scene) -> Arc<Vec<u8>> {
+        if let Some(png) = self.pngs.lock().unwrap().pngs.get(scene) {
+            return Arc::clone(png);
+        }
+
+        let png = Arc::new(self.encode(&self.draw(scene)));
+
+        let mut cache = self.pngs.lock().unwrap();
+
+        if cache.pngs.insert(scene.clone(), Arc::clone(&png)).is_none() {
+            cache.order.push_back(scene.clone());
+        }
+
+        if cache.order.len() > PNG_CACHE_SIZE {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.pngs.remove(&oldest);
+            }
+        }
+
+        png
+    }
+
+    // Animated GIF of the game with a frame per move, the last frame stays longer
+    pub fn gif(&self, mode: Mode, moves: &[usize], theme: &str) -> Vec<u8> {
+        let mut state = Variant::new(mode);
+        let mut scene = Scene::new(&state, theme);
+        let mut images = vec![self.draw(&scene)];
+
+        for index in moves {
+            if state.play(*index).is_err() {
+                break;
+            }
+
+            scene = Scene::new(&state, theme).last_move(*index);
+            images.push(self.draw(&scene));
+        }
+
+        if let Outcome::Win(_, line) = state.outcome() {
+            images.push(self.draw(&scene.win_line(line)));
+        }
+
+        let last = images.len() - 1;
+        let frames = images.into_iter().enumerate().map(|(index, image)| {
+            let delay = if index == last {
+                REPLAY_LAST_FRAME_DELAY
+            } else {
+                REPLAY_FRAME_DELAY
+            };
+
+            Frame::from_parts(
+                DynamicImage::ImageRgb8(image).into_rgba8(),
+                0,
+                0,
+                Delay::from_numer_denom_ms(delay, 1),
+            )
+        });
+
+        let start = Instant::now();
+        let mut buffer = Vec::new();
+
+        {
+            let mut encoder = GifEncoder::new_with_speed(&mut buffer, REPLAY_GIF_SPEED);
+
+            encoder
+                .set_repeat(Repeat::Infinite)
+                .expect("failed to write in buffer");
+            encoder
+                .encode_frames(frames)
+                .expect("failed to write in buffer");
+        }
+
+        self.record(Stage::Encode, start.elapsed());
+
+        buffer
+    }
+
+    pub fn draw(&self, scene: &Scene) -> RgbImage {
+        let start = Instant::now();
+        let canvas = self.draw_scene(scene);
+        self.record(Stage::Draw, start.elapsed());
+
+        canvas
+    }
+
+    pub fn encode(&self, image: &RgbImage) -> Vec<u8> {
+        let start = Instant::now();
+        let png = encode_png(image);
+        self.record(Stage::Encode, start.elapsed());
+
+        png
+    }
+
+    pub fn record(&self, stage: Stage, elapsed: Duration) {
+        let mut metrics = self.metrics.lock().unwrap();
+
+        match stage {
+            Stage::Wait => &mut metrics.wait,
+            Stage::Draw => &mut metrics.draw,
+            Stage::Encode => &mut metrics.encode,
+        }
+        .add(elapsed);
+    }
+
+    pub fn record_load(&self, jobs: usize) {
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.peak = metrics.peak.max(jobs);
+    }
+
+    pub fn record_rejection(&self) {
+        self.metrics.lock().unwrap().rejected += 1;
+    }
+
+    pub fn themes(&self) -> &Themes {
+        &self.themes
+    }
+
+    // Of the labels and the leaderboards
+    pub fn font(&self) -> Option<&Font<'static>> {
+        self.font.as_ref()
+    }
+
+    // Metrics since the last call, the counting starts over
+    pub fn take_metrics(&self) -> Metrics {
+        mem::take(&mut self.metrics.lock().unwrap())
+    }
+
+    fn draw_scene(&self, scene: &Scene) -> RgbImage {
+        let theme = self.themes.get(&scene.theme);
+        let scale = scene.scale;
+        let mut canvas = draw_empty_board(scene.mode, theme, scale);
+
+        // Under the piece, so the piece keeps its colors
+        if let Some(index) = scene.last_move {
+            let (layout, cell) = cell_layout(scene.mode, index, scale);
+            draw_last_move(&mut canvas, layout, cell, theme.highlight);
+        }
+
+        for (index, cell) in scene.cells.iter().enumerate() {
+            let Cell::Taken(player) = *cell else {
+                continue;
+            };
+
+            let (layout, cell) = cell_layout(scene.mode, index, scale);
+
+            let piece = match player {
+                Player::First => &theme.x,
+                Player::Second => &theme.o,
+            };
+
+            match piece {
+                Piece::Sprite { sprite } => {
+                    self.draw_sprite(&mut canvas, sprite, layout, cell);
+                }
+                Piece::Shape { shape, color } => {
+                    draw_shape(&mut canvas, *shape, *color, layout, cell);
+                }
+            }
+        }
+
+        for (board, line) in &scene.local_lines {
+            draw_strike(
+                &mut canvas,
+                Layout::local(*board, scale),
+                *line,
+                &theme.strike,
+            );
+        }
+
+        if let Some(cursor_pos) = scene.outline {
+            draw_select_outline(&mut canvas, scene.mode, cursor_pos, theme.highlight, scale);
+        }
+
+        if let Some(line) = scene.win_line {
+            draw_strike(
+                &mut canvas,
+                Layout::board(scene.mode, scale),
+                line,
+                &theme.strike,
+            );
+        }
+
+        match &self.font {
+            Some(font) if scene.labels => add_labels(&canvas, scene.mode, theme, font, scale),
+            _ => canvas,
+        }
+    }
+
+    fn draw_sprite(&self, canvas: &mut RgbImage, sprite: &PathBuf, layout: Layout, cell: usize) {
+        let Some(original) = self.sprites.get(sprite) else {
+            return; // Not loaded, the renderer is made without the themes
+        };
+
+        let size = layout.cell * 8 / 10;
+        let margin = layout.cell / 10;
+
+        let image = self
+            .sprites_scaled
+            .lock()
+            .unwrap()
+            .entry((sprite.clone(), size))
+            .or_insert_with(|| {
+                if original.width() == size {
+                    Arc::new(original.clone())
+                } else {
+                    Arc::new(imageops::resize(original, size, size, FilterType::Triangle))
+                }
+            })
+            .clone();
+
+        let (left, top) = layout.cell_origin(cell);
+
+        blend(
+            canvas,
+            &image,
+            left + margin,
+            top + margin,
+            0..size,
+            0..size,
+        );
+    }
+}
+
+impl Metrics {
+    pub fn is_empty(&self) -> bool {
+        self.wait.count == 0 && self.rejected == 0
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "wait {}, draw {}, encode {}, peak {} jobs, {} rejected",
+            self.wait, self.draw, self.encode, self.peak, self.rejected,
+        )
+    }
+}
+
+impl Latency {
+    fn add(&mut self, elapsed: Duration) {
+        self.count += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "none");
+        }
+
+        write!(
+            f,
+            "{} times, {:?} on average, {:?} at most",
+            self.count,
+            self.total / self.count,
+            self.max,
+        )
+    }
+}
+
+fn encode_png(image: &RgbImage) -> Vec<u8> {
+    let mut buffer = Cursor::new(Vec::new());
+
+    image
+        .write_to(&mut buffer, ImageOutputFormat::Png)
+        .expect("failed to write in buffer");
+
+    buffer.into_inner()
+}
+
+// Part of the overlay is mixed into the canvas row by row, the overlay lies
+// at the given place of the canvas
+fn blend(
+    canvas: &mut RgbImage,
+    overlay: &RgbaImage,
+    left: u32,
+    top: u32,
+    columns: Range<u32>,
+    rows: Range<u32>,
+) {
+    let canvas_row = canvas.width() as usize * 3;
+    let overlay_row = overlay.width() as usize * 4;
+    let (from, to) = (columns.start as usize, columns.end as usize);
+
+    let pixels: &mut [u8] = canvas;
+    let overlay: &[u8] = overlay;
+
+    for y in rows {
+        let start = overlay_row * y as usize + from * 4;
+        let source = &overlay[start..start + (to - from) * 4];
+
+        let start = (top + y) as usize * canvas_row + (left as usize + from) * 3;
+        let target = &mut pixels[start..start + (to - from) * 3];
+
+        for (pixel, overlay) in target.chunks_exact_mut(3).zip(source.chunks_exact(4)) {
+            mix(pixel, overlay);
+        }
+    }
+}
+
+// Overlay pixel over the canvas one by its transparency
+fn mix(pixel: &mut [u8], overlay: &[u8]) {
+    let alpha = overlay[3] as u32;
+
+    match alpha {
+        0 => {}
+        255 => pixel.copy_from_slice(&overlay[..3]),
+        _ => {
+            for (channel, value) in pixel.iter_mut().zip(overlay) {
+                *channel =
+                    ((*channel as u32 * (255 - alpha) + *value as u32 * alpha + 127) / 255) as u8;
+            }
+        }
+    }
+}
+
+// Geometry of the board picture
+#[derive(Clone, Copy)]
+struct Layout {
+    left: u32, // Position of the board on the canvas
+    top: u32,
+    size: u32, // Count of cells in a row
+    cell: u32, // Side of a cell including the grid lines
+    line: u32, // Width of the grid lines
+}
+
+impl Layout {
+    // Whole board, the big one of the ultimate game
+    fn board(mode: Mode, scale: u32) -> Self {
+        match mode {
+            Mode::Classic(rules) => {
+                let size = rules.size as u32;
+                let cell = (MAX_CANVAS_SIZE / size).min(CELL_SIZE);
+
+                Self {
+                    left: 0,
+                    top: 0,
+                    size,
+                    cell: cell * scale,
+                    line: (cell / 25).max(2) * scale,
+                }
+            }
+
+            Mode::Ultimate => Self {
+                left: 0,
+                top: 0,
+                size: 3,
+                cell: ULTIMATE_PLACE_SIZE * scale,
+                line: 6 * scale,
+            },
+        }
+    }
+
+    // Local board of the ultimate game
+    fn local(board: usize, scale: u32) -> Self {
+        let (left, top) = Self::board(Mode::Ultimate, scale).cell_origin(board);
+
+        Self {
+            left: left + ULTIMATE_MARGIN * scale,
+            top: top + ULTIMATE_MARGIN * scale,
+            size: 3,
+            cell: ULTIMATE_LOCAL_CELL_SIZE * scale,
+            line: 2 * scale,
+        }
+    }
+
+    fn side(&self) -> u32 {
+        self.cell * self.size
+    }
+
+    fn cell_origin(&self, cell: usize) -> (u32, u32) {
+        let cell = cell as u32;
+
+        (
+            self.left + cell % self.size * self.cell,
+            self.top + cell / self.size * self.cell,
+        )
+    }
+
+    fn cell_center(&self, cell: usize) -> draw::Point {
+        let (left, top) = self.cell_origin(cell);
+        let half = self.cell as f32 / 2.0;
+
+        (left as f32 + half, top as f32 + half)
+    }
+}
+
+// Layout of the board with the cell and the index of the cell in it
+fn cell_layout(mode: Mode, index: usize, scale: u32) -> (Layout, usize) {
+    match mode {
+        Mode::Classic(_) => (Layout::board(mode, scale), index),
+        Mode::Ultimate => {
+            let (board, cell) = ultimate::split_index(index);
+            (Layout::local(board, scale), cell)
+        }
+    }
+}
+
+fn draw_empty_board(mode: Mode, theme: &Theme, scale: u32) -> RgbImage {
+    let layout = Layout::board(mode, scale);
+
+    let side = layout.side();
+    let mut canvas = ImageBuffer::from_pixel(side, side, theme.background);
+
+    draw_grid(&mut canvas, layout, theme.grid);
+
+    if mode == Mode::Ultimate {
+        for board in 0..ultimate::BOARD_COUNT {
+            draw_grid(&mut canvas, Layout::local(board, scale), theme.grid);
+        }
+    }
+
+    canvas
+}
+
+fn draw_grid(canvas: &mut RgbImage, layout: Layout, color: Rgb<u8>) {
+    let (left, top) = (layout.left as f32, layout.top as f32);
+    let (side, line) = (layout.side() as f32, layout.line as f32);
+
+    for i in 1..layout.size {
+        let offset = (i * layout.cell) as f32 - line / 2.0;
+
+        draw::fill_rect(canvas, left + offset, top, line, side, color);
+        draw::fill_rect(canvas, left, top + offset, side, line, color);
+    }
+}
+
+// Line goes a bit past the centers of the end cells
+fn draw_strike(canvas: &mut RgbImage, layout: Layout, line: Line, strike: &Strike) {
+    let (x1, y1) = layout.cell_center(line.start);
+    let (x2, y2) = layout.cell_center(line.end);
+
+    let length = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt().max(1.0);
+    let overshoot = layout.cell as f32 * 0.3 / length;
+    let (dx, dy) = ((x2 - x1) * overshoot, (y2 - y1) * overshoot);
+
+    let width = (layout.cell as f32 * 0.08).max(layout.line as f32 * 2.0);
+    let (from, to) = ((x1 - dx, y1 - dy), (x2 + dx, y2 + dy));
+    let color = strike.color.unwrap_or(STRIKE);
+
+    match strike.style {
+        StrikeStyle::Line => draw::stroke_segment(canvas, from, to, width, color),
+        StrikeStyle::Scratch => draw::stroke_scratch(canvas, from, to, width, color),
+    }
+}
+
+// Pieces of the themes without sprites fill the same part of the cell as the sprites
+fn draw_shape(canvas: &mut RgbImage, shape: Shape, color: Rgb<u8>, layout: Layout, cell: usize) {
+    let center = layout.cell_center(cell);
+    let size = layout.cell as f32;
+    let width = size * 0.12;
+
+    match shape {
+        Shape::Cross => draw::stroke_cross(canvas, center, size * 0.3, width, color),
+        Shape::Ring => draw::stroke_circle(canvas, center, size * 0.33, width, color),
+    }
+}
+
+// Inside of the cell between the grid lines, blended like the sprites
+fn draw_last_move(canvas: &mut RgbImage, layout: Layout, cell: usize, color: Rgb<u8>) {
+    let (left, top) = layout.cell_origin(cell);
+    let (from, to) = (
+        layout.line / 2,
+        layout.cell - (layout.line - layout.line / 2),
+    );
+
+    let Rgb([r, g, b]) = color;
+    let overlay = [r, g, b, LAST_MOVE_ALPHA];
+
+    for y in top + from..top + to {
+        for x in left + from..left + to {
+            mix(&mut canvas.get_pixel_mut(x, y).0, &overlay);
+        }
+    }
+}
+
+fn draw_select_outline(
+    canvas: &mut RgbImage,
+    mode: Mode,
+    cursor_pos: CursorPos,
+    color: Rgb<u8>,
+    scale: u32,
+) {
+    let (layout, cell) = match (mode, cursor_pos) {
+        (Mode::Classic(_), CursorPos::Cell(cell)) => (Layout::board(mode, scale), cell),
+        (_, CursorPos::Board(board)) => (Layout::board(mode, scale), board),
+        (_, CursorPos::LocalCell(board, cell)) => (Layout::local(board, scale), cell),
+        _ => unreachable!(),
+    };
+
+    let (left, top) = layout.cell_origin(cell);
+    let (size, line) = (layout.cell as f32, layout.line as f32);
+
+    // Outline lies over the grid lines, the canvas cuts it at the edges
+    let (left, top) = (left as f32 - line / 2.0, top as f32 - line / 2.0);
+    let side = size + line;
+
+    draw::fill_rect(canvas, left, top, side, line, color);
+    draw::fill_rect(canvas, left, top + side - line, side, line, color);
+    draw::fill_rect(canvas, left, top, line, side, color);
+    draw::fill_rect(canvas, left + side - line, top, line, side, color);
+}
+
+// Board moved to the corner of a bigger canvas with the labels in the margins,
+// letters and numbers are the ones of the notation
+fn add_labels(board: &RgbImage, mode: Mode, theme: &Theme, font: &Font, scale: u32) -> RgbImage {
+    let margin = LABEL_MARGIN * scale;
+    let side = board.width() + margin;
+
+    let mut canvas = ImageBuffer::from_pixel(side, side, theme.background);
+    imageops::replace(&mut canvas, board, margin as i64, margin as i64);
+
+    let text_scale = Scale::uniform(LABEL_SIZE * scale as f32);
+    let middle = margin as f32 / 2.0;
+
+    let offsets = label_offsets(mode, scale);
+    let letters: String = (b'a'..).take(offsets.len()).map(char::from).collect();
+
+    // Board is square, so the rows are at the same offsets as the columns
+    for (index, offset) in offsets.into_iter().enumerate() {
+        let offset = margin as f32 + offset;
+        let letter = &letters[index..index + 1];
+        let number = (index + 1).to_string();
+
+        // Letters stand on one line, each number is centered on its own
+        let (x, y) = ((offset, middle), (middle, offset));
+        draw_label(
+            &mut canvas,
+            x,
+            letter,
+            &letters,
+            theme.grid,
+            text_scale,
+            font,
+        );
+        draw_label(
+            &mut canvas,
+            y,
+            &number,
+            &number,
+            theme.grid,
+            text_scale,
+            font,
+        );
+    }
+
+    canvas
+}
+
+// Centers of the columns from the left of the board
+fn label_offsets(mode: Mode, scale: u32) -> Vec<f32> {
+    match mode {
+        Mode::Classic(rules) => {
+            let layout = Layout::board(mode, scale);
+            (0..rules.size)
+                .map(|cell| layout.cell_center(cell).0)
+                .collect()
+        }
+
+        Mode::Ultimate => (0..9)
+            .map(|column| Layout::local(column / 3, scale).cell_center(column % 3).0)
+            .collect(),
+    }
+}
+
+// Text centered on the point, the glyphs of the whole line set its height
+fn draw_label(
+    canvas: &mut RgbImage,
+    center: draw::Point,
+    text: &str,
+    line: &str,
+    color: Rgb<u8>,
+    scale: Scale,
+    font: &Font,
+) {
+    let (Some(glyphs), Some(row)) = (
+        text_bounds(font, scale, text),
+        text_bounds(font, scale, line),
+    ) else {
+        return; // Nothing to draw
+    };
+
+    let x = center.0.round() as i32 - (glyphs.min.x + glyphs.max.x) / 2;
+    let y = center.1.round() as i32 - (row.min.y + row.max.y) / 2;
+
+    draw_text_mut(canvas, color, x, y, scale, font, text);
+}
+
+// Pixels covered by the glyphs, placed the same way as draw_text_mut places them
+fn text_bounds(font: &Font, scale: Scale, text: &str) -> Option<Rect<i32>> {
+    let ascent = font.v_metrics(scale).ascent;
+
+    font.layout(text, scale, point(0.0, ascent))
+        .filter_map(|glyph| glyph.pixel_bounding_box())
+        .reduce(|a, b| Rect {
+            min: point(a.min.x.min(b.min.x), a.min.y.min(b.min.y)),
+            max: point(a.max.x.max(b.max.x), a.max.y.max(b.max.y)),
+        })
+}
@@ -0,0 +1,76 @@
+// Running games saved to the disk after every move, so a restart of the bot
+// doesn't strand the players. Interactions can't be saved, their tokens
+// expire, so the players get new game panels after the restart.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::{ChannelId, MessageId, UserId};
+
+use crate::ai::Difficulty;
+use crate::clock::TimeControl;
+use crate::engine::Mode;
+use crate::input::Input;
+use crate::storage::JsonFile;
+
+// Cursor isn't saved: the games are saved after the moves and the takebacks,
+// which put it back to the start anyway, and a press of an arrow isn't worth
+// writing the file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub player: (UserId, String, ChannelId, MessageId), // Message is the common one
+    pub player2: (UserId, String, Option<(ChannelId, MessageId)>),
+    pub bot: Option<Difficulty>,
+
+    pub mode: Mode,
+    pub moves: Vec<usize>, // Indices for `Variant::play`
+    pub times: Vec<u64>,   // Milliseconds since the start of the game for every move
+    pub elapsed: Duration, // Since the start of the game
+    pub input: (Input, Input),
+    pub clock: Option<(TimeControl, Duration, Duration)>, // Remaining time of both players
+    #[serde(default)]
+    pub theme: String,               // Games saved before the themes look like the default one
+    #[serde(default)]
+    pub labels: bool,
+    #[serde(default)]
+    pub version: u64, // Of the panels, the ones from before the restart stay outdated
+}
+
+// There are a few games at once, so the file is rewritten after every move
+#[derive(Default)]
+pub struct SavedSessions {
+    file: JsonFile,
+    sessions: Vec<SavedSession>,
+}
+
+impl SavedSessions {
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let file = JsonFile::new(path);
+
+        Self {
+            sessions: file.load(),
+            file,
+        }
+    }
+
+    fn save(&mut self) {
+        self.file.save(&self.sessions);
+    }
+
+    pub fn sessions(&self) -> &[SavedSession] {
+        &self.sessions
+    }
+
+    // The first player is in one game at most, so he/she/they identifies it
+    pub fn put(&mut self, session: SavedSession) {
+        self.sessions.retain(|val| val.player.0 != session.player.0);
+        self.sessions.push(session);
+        self.save();
+    }
+
+    pub fn remove(&mut self, user_id: UserId) {
+        self.sessions.retain(|val| val.player.0 != user_id);
+        self.save();
+    }
+}
@@ -0,0 +1,155 @@
+// Running games indexed by their players and messages. Lookups don't touch
+// the games themselves, so a busy game doesn't hold up the others.
+
+use std::collections::HashMap;
+
+use serenity::model::prelude::{MessageId, UserId};
+
+struct Entry<T> {
+    session: T,
+    users: Vec<UserId>,
+    messages: Vec<MessageId>,
+}
+
+pub struct Sessions<T> {
+    games: HashMap<MessageId, Entry<T>>, // By the common message of the game
+    users: HashMap<UserId, MessageId>,
+    messages: HashMap<MessageId, MessageId>, // Every message of the game
+}
+
+impl<T> Default for Sessions<T> {
+    fn default() -> Self {
+        Self {
+            games: HashMap::new(),
+            users: HashMap::new(),
+            messages: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Sessions<T> {
+    // A player is in one game at most. The bot plays many games at once, so
+    // it mustn't be among the users.
+    pub fn insert(
+        &mut self,
+        game: MessageId,
+        users: Vec<UserId>,
+        messages: Vec<MessageId>,
+        session: T,
+    ) {
+        for user_id in &users {
+            self.users.insert(*user_id, game);
+        }
+
+        for message_id in &messages {
+            self.messages.insert(*message_id, game);
+        }
+
+        let entry = Entry {
+            session,
+            users,
+            messages,
+        };
+
+        self.games.insert(game, entry);
+    }
+
+    pub fn remove(&mut self, game: MessageId) -> Option<T> {
+        let entry = self.games.remove(&game)?;
+
+        for user_id in &entry.users {
+            self.users.remove(user_id);
+        }
+
+        for message_id in &entry.messages {
+            self.messages.remove(message_id);
+        }
+
+        Some(entry.session)
+    }
+
+    pub fn by_user(&self, user_id: UserId) -> Option<&T> {
+        let game = self.users.get(&user_id)?;
+
+        self.games.get(game).map(|entry| &entry.session)
+    }
+
+    pub fn by_message(&self, message_id: MessageId) -> Option<&T> {
+        let game = self.messages.get(&message_id)?;
+
+        self.games.get(game).map(|entry| &entry.session)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.games.values().map(|entry| &entry.session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: u64) -> UserId {
+        UserId::new(id)
+    }
+
+    fn message(id: u64) -> MessageId {
+        MessageId::new(id)
+    }
+
+    // Game against a player in another channel and a game against the bot
+    fn sessions() -> Sessions<&'static str> {
+        let mut sessions = Sessions::default();
+
+        sessions.insert(
+            message(10),
+            vec![user(1), user(2)],
+            vec![message(10), message(11)],
+            "pvp",
+        );
+        sessions.insert(message(20), vec![user(3)], vec![message(20)], "bot");
+
+        sessions
+    }
+
+    #[test]
+    fn games_are_found_by_every_player_and_message() {
+        let sessions = sessions();
+
+        assert_eq!(sessions.by_user(user(1)), Some(&"pvp"));
+        assert_eq!(sessions.by_user(user(2)), Some(&"pvp"));
+        assert_eq!(sessions.by_message(message(11)), Some(&"pvp"));
+        assert_eq!(sessions.by_message(message(20)), Some(&"bot"));
+        assert_eq!(sessions.by_user(user(4)), None);
+    }
+
+    #[test]
+    fn removed_game_leaves_both_indices() {
+        let mut sessions = sessions();
+
+        assert_eq!(sessions.remove(message(10)), Some("pvp"));
+        assert_eq!(sessions.remove(message(10)), None);
+
+        for user_id in [user(1), user(2)] {
+            assert_eq!(sessions.by_user(user_id), None);
+        }
+
+        for message_id in [message(10), message(11)] {
+            assert_eq!(sessions.by_message(message_id), None);
+        }
+
+        assert_eq!(sessions.by_user(user(3)), Some(&"bot"));
+        assert_eq!(sessions.iter().count(), 1);
+    }
+
+    #[test]
+    fn player_of_a_new_game_points_to_it() {
+        let mut sessions = sessions();
+
+        sessions.remove(message(20));
+        sessions.insert(message(30), vec![user(3)], vec![message(30)], "rematch");
+
+        assert_eq!(sessions.by_user(user(3)), Some(&"rematch"));
+        assert_eq!(sessions.by_message(message(20)), None);
+    }
+}
@@ -0,0 +1,393 @@
+// Results of the finished games. Every record is a line of JSON appended to
+// the file, so a crash in the middle of a write costs only that one game.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::{GuildId, UserId};
+
+use crate::clock::TimeControl;
+use crate::engine::{Mode, Player};
+use crate::rating::{self, Ratings};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Ending {
+    Board, // Line of pieces or the full board
+    Resignation,
+    Time,      // The player on turn has run out of time
+    Agreement, // Draw offered by one player and accepted by another
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub id: u64,
+    pub player: (UserId, String), // Plays first
+    pub player2: (UserId, String),
+    pub mode: Mode,
+    pub winner: Option<Player>, // No one wins in a draw
+    pub ending: Ending,
+    pub moves: Vec<usize>, // Indices for `Variant::play`
+    pub timestamp: u64,    // Seconds since the Unix epoch
+
+    #[serde(default)]
+    pub bot: bool, // Games against the bot don't change the ratings
+    #[serde(default)]
+    pub guild: Option<GuildId>, // Guild of the first player
+    #[serde(default)]
+    pub times: Vec<u64>, // Milliseconds since the start of the game for every move
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+}
+
+impl GameRecord {
+    // Result of the game for the given player, `None` if he/she/they didn't play it
+    fn result_for(&self, user_id: UserId) -> Option<GameResult> {
+        let side = if self.player.0 == user_id {
+            Player::First
+        } else if self.player2.0 == user_id {
+            Player::Second
+        } else {
+            return None;
+        };
+
+        Some(match self.winner {
+            Some(winner) if winner == side => GameResult::Win,
+            Some(_) => GameResult::Loss,
+            None => GameResult::Draw,
+        })
+    }
+
+    fn opponent_of(&self, user_id: UserId) -> &(UserId, String) {
+        if self.player.0 == user_id {
+            &self.player2
+        } else {
+            &self.player
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    Win,
+    Loss,
+    Draw,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Score {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Score {
+    fn add(&mut self, result: GameResult) {
+        match result {
+            GameResult::Win => self.wins += 1,
+            GameResult::Loss => self.losses += 1,
+            GameResult::Draw => self.draws += 1,
+        }
+    }
+
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+}
+
+pub struct Summary {
+    pub rating: f64,
+    pub score: Score,
+    pub streak: Option<(GameResult, u32)>, // Results of the latest games in a row
+    pub best_streak: u32,                  // The longest run of wins
+    pub head_to_head: Vec<(String, Score)>, // The most frequent opponents go first
+}
+
+pub struct Standing {
+    pub user_id: UserId,
+    pub name: String,
+    pub rating: f64,
+    pub gain: f64, // Change of the rating in the counted games
+    pub score: Score,
+}
+
+// Order of the players in the leaderboard
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Ranking {
+    #[default]
+    Rating,
+    Wins,
+}
+
+impl Ranking {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rating" => Some(Ranking::Rating),
+            "wins" => Some(Ranking::Wins),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Ranking::Rating => "rating",
+            Ranking::Wins => "wins",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Stats {
+    path: PathBuf,
+    records: Vec<GameRecord>, // Oldest first
+    ratings: Ratings,
+
+    unwritten: Arc<Mutex<Vec<String>>>, // Lines of the games waiting for the disk, oldest first
+    writing: Arc<Mutex<()>>,            // Held by the write, so the lines keep their order
+}
+
+impl Stats {
+    // Missing file is a new store, broken lines are skipped
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+
+        let records: Vec<GameRecord> = fs::read_to_string(&path)
+            .map(|text| {
+                text.lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut stats = Self {
+            path,
+            ..Default::default()
+        };
+
+        for record in &records {
+            stats.rate(record);
+        }
+
+        stats.records = records;
+        stats
+    }
+
+    pub fn rating(&self, user_id: UserId) -> f64 {
+        self.ratings.get(user_id)
+    }
+
+    // Changes of the ratings of both players, `None` for the game against the bot
+    pub fn rate(&mut self, record: &GameRecord) -> Option<(rating::Change, rating::Change)> {
+        rate_game(&mut self.ratings, record)
+    }
+
+    pub fn game(&self, id: u64) -> Option<&GameRecord> {
+        self.records.iter().find(|record| record.id == id)
+    }
+
+    pub fn last_game_of(&self, user_id: UserId) -> Option<&GameRecord> {
+        self.records
+            .iter()
+            .rev()
+            .find(|record| record.result_for(user_id).is_some())
+    }
+
+    pub fn next_id(&self) -> u64 {
+        self.records.last().map_or(1, |record| record.id + 1)
+    }
+
+    // Game is kept in memory even if the file fails, so its id isn't given
+    // out again and the ratings match the rated games
+    pub fn record(&mut self, record: GameRecord) {
+        match serde_json::to_string(&record) {
+            Ok(line) => {
+                self.unwritten.lock().unwrap().push(line);
+                self.append();
+            }
+            Err(err) => eprintln!("Failed to save the game: {err:?}"),
+        }
+
+        self.records.push(record);
+    }
+
+    // File is written on the threads for the blocking work, so the handlers
+    // don't wait for the disk. Lines which have failed are tried again with
+    // the next game.
+    fn append(&self) {
+        let (path, unwritten, writing) = (
+            self.path.clone(),
+            self.unwritten.clone(),
+            self.writing.clone(),
+        );
+
+        tokio::task::spawn_blocking(move || {
+            let _writing = writing.lock().unwrap();
+            let lines = mem::take(&mut *unwritten.lock().unwrap());
+
+            if lines.is_empty() {
+                return;
+            }
+
+            let text: String = lines.iter().map(|line| format!("{line}\n")).collect();
+
+            let result = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(text.as_bytes()));
+
+            if let Err(err) = result {
+                eprintln!("Failed to save the games: {err:?}");
+                unwritten.lock().unwrap().splice(0..0, lines);
+            }
+        });
+    }
+
+    pub fn summary(&self, user_id: UserId) -> Summary {
+        let mut score = Score::default();
+        let mut streak: Option<(GameResult, u32)> = None;
+        let mut best_streak = 0;
+
+        // Latest name of the opponent is shown
+        let mut head_to_head: HashMap<UserId, (String, Score)> = HashMap::new();
+
+        for record in &self.records {
+            let Some(result) = record.result_for(user_id) else {
+                continue;
+            };
+
+            score.add(result);
+
+            streak = match streak {
+                Some((last, count)) if last == result => Some((result, count + 1)),
+                _ => Some((result, 1)),
+            };
+
+            if let Some((GameResult::Win, count)) = streak {
+                best_streak = best_streak.max(count);
+            }
+
+            let (opponent, name) = record.opponent_of(user_id);
+            let entry = head_to_head
+                .entry(*opponent)
+                .or_insert_with(|| (String::new(), Score::default()));
+
+            entry.0 = name.clone();
+            entry.1.add(result);
+        }
+
+        let mut head_to_head: Vec<_> = head_to_head.into_values().collect();
+        head_to_head.sort_by(|a, b| b.1.games().cmp(&a.1.games()).then_with(|| a.0.cmp(&b.0)));
+
+        Summary {
+            rating: self.rating(user_id),
+            score,
+            streak,
+            best_streak,
+            head_to_head,
+        }
+    }
+
+    // Players of the games in the guild since the given time, the games
+    // against the bot aren't counted. Over a period the players are ranked by
+    // the rating gained in it, not by the one earned long before
+    pub fn leaderboard(
+        &self,
+        guild_id: GuildId,
+        since: Option<u64>,
+        ranking: Ranking,
+    ) -> Vec<Standing> {
+        let mut standings: Vec<Standing> = Vec::new();
+
+        // Ratings are replayed for the changes in every game
+        let mut ratings = Ratings::default();
+
+        for record in &self.records {
+            let changes = rate_game(&mut ratings, record);
+
+            let is_counted = !record.bot
+                && record.guild == Some(guild_id)
+                && record.timestamp >= since.unwrap_or(0);
+
+            if !is_counted {
+                continue;
+            }
+
+            let players = [
+                (&record.player, changes.map(|val| val.0)),
+                (&record.player2, changes.map(|val| val.1)),
+            ];
+
+            for ((user_id, name), change) in players {
+                let pos = match standings.iter().position(|val| val.user_id == *user_id) {
+                    Some(pos) => pos,
+                    None => {
+                        standings.push(Standing {
+                            user_id: *user_id,
+                            name: String::new(),
+                            rating: self.rating(*user_id),
+                            gain: 0.0,
+                            score: Score::default(),
+                        });
+
+                        standings.len() - 1
+                    }
+                };
+
+                let standing = &mut standings[pos];
+
+                // Latest name is shown
+                standing.name = name.clone();
+
+                if let Some(result) = record.result_for(*user_id) {
+                    standing.score.add(result);
+                }
+
+                if let Some(change) = change {
+                    standing.gain += change.new - change.old;
+                }
+            }
+        }
+
+        match ranking {
+            Ranking::Rating => match since {
+                Some(_) => standings.sort_by(|a, b| b.gain.total_cmp(&a.gain)),
+                None => standings.sort_by(|a, b| b.rating.total_cmp(&a.rating)),
+            },
+            Ranking::Wins => standings.sort_by(|a, b| {
+                (b.score.wins, a.score.losses).cmp(&(a.score.wins, b.score.losses))
+            }),
+        }
+
+        standings
+    }
+}
+
+// Same for the live ratings and the ones replayed for the leaderboards
+fn rate_game(
+    ratings: &mut Ratings,
+    record: &GameRecord,
+) -> Option<(rating::Change, rating::Change)> {
+    if record.bot {
+        return None;
+    }
+
+    let score = match record.winner {
+        Some(Player::First) => 1.0,
+        Some(Player::Second) => 0.0,
+        None => 0.5,
+    };
+
+    Some(ratings.update(record.player.0, record.player2.0, score))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
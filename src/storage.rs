@@ -0,0 +1,70 @@
+// Small state files of the bot: the pinned leaderboards, the theme choices,
+// the queue policies and the running games. Every file is JSON written as a
+// whole after a change. It is written on the threads for the blocking work, so
+// the handlers don't wait for the disk, and replaces the old one only when it
+// is complete, so a crash in the middle of a write doesn't lose the state.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[derive(Default)]
+pub struct JsonFile {
+    path: PathBuf,            // Nothing is written without it, like in the tests
+    version: u64,             // Of the latest change
+    written: Arc<Mutex<u64>>, // Version in the file, the older ones finished late are dropped
+}
+
+impl JsonFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            ..Default::default()
+        }
+    }
+
+    // Missing or broken file is an empty state
+    pub fn load<T: DeserializeOwned + Default>(&self) -> T {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save<T: Serialize>(&mut self, value: &T) {
+        if self.path.as_os_str().is_empty() {
+            return;
+        }
+
+        let text = match serde_json::to_string(value) {
+            Ok(text) => text,
+            Err(err) => {
+                eprintln!("Failed to save {}: {err:?}", self.path.display());
+                return;
+            }
+        };
+
+        self.version += 1;
+
+        let (path, version, written) = (self.path.clone(), self.version, self.written.clone());
+
+        tokio::task::spawn_blocking(move || {
+            let mut written = written.lock().unwrap();
+
+            if *written > version {
+                return;
+            }
+
+            let temp = path.with_extension("json.tmp");
+            let result = fs::write(&temp, text).and_then(|()| fs::rename(&temp, &path));
+
+            match result {
+                Ok(()) => *written = version,
+                Err(err) => eprintln!("Failed to save {}: {err:?}", path.display()),
+            }
+        });
+    }
+}
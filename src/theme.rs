@@ -0,0 +1,227 @@
+// Looks of the boards. Themes are TOML files in the themes directory, the
+// name of the file is the id of the theme. Players choose the theme of their
+// games, servers choose one for the players who haven't.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::Rgb;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use serenity::model::prelude::{GuildId, UserId};
+
+use crate::storage::JsonFile;
+
+pub const DEFAULT_THEME: &str = "classic";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Theme {
+    pub name: String, // Shown to the players
+    #[serde(deserialize_with = "color")]
+    pub background: Rgb<u8>,
+    #[serde(deserialize_with = "color")]
+    pub grid: Rgb<u8>, // Text of the leaderboard too
+    #[serde(deserialize_with = "color")]
+    pub highlight: Rgb<u8>, // Outline of the selected cell or board
+    pub x: Piece,
+    pub o: Piece,
+    #[serde(default)]
+    pub strike: Strike,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Piece {
+    Sprite {
+        sprite: PathBuf,
+    },
+    Shape {
+        shape: Shape,
+        #[serde(deserialize_with = "color")]
+        color: Rgb<u8>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shape {
+    Cross,
+    Ring,
+}
+
+// Line through the winning pieces
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Strike {
+    #[serde(default)]
+    pub style: StrikeStyle,
+    #[serde(default, deserialize_with = "optional_color")]
+    pub color: Option<Rgb<u8>>, // Blue without it
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StrikeStyle {
+    #[default]
+    Line,
+    Scratch, // Looks hand-drawn
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            name: "Classic".to_string(),
+            background: Rgb([42, 44, 47]),
+            grid: Rgb([232, 232, 232]),
+            highlight: Rgb([196, 57, 57]),
+            x: Piece::Shape {
+                shape: Shape::Cross,
+                color: Rgb([240, 240, 0]),
+            },
+            o: Piece::Shape {
+                shape: Shape::Ring,
+                color: Rgb([255, 30, 85]),
+            },
+            strike: Strike::default(),
+        }
+    }
+}
+
+// Every theme found at the start, the classic one is there even without its file
+pub struct Themes {
+    themes: BTreeMap<String, Theme>,
+}
+
+impl Themes {
+    pub fn open(dir: impl AsRef<Path>) -> Self {
+        let mut themes = BTreeMap::new();
+        themes.insert(DEFAULT_THEME.to_string(), Theme::default());
+
+        let entries = fs::read_dir(dir).into_iter().flatten().flatten();
+
+        for entry in entries {
+            let path = entry.path();
+
+            if path.extension() != Some(OsStr::new("toml")) {
+                continue;
+            }
+
+            let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            let theme = fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|text| toml::from_str::<Theme>(&text).map_err(|err| err.to_string()));
+
+            match theme {
+                Ok(theme) => {
+                    themes.insert(id.to_string(), theme);
+                }
+                Err(err) => eprintln!("Failed to load the theme {}: {}", path.display(), err),
+            }
+        }
+
+        Self { themes }
+    }
+
+    // Unknown themes, removed since the choice or never loaded, look like the default one
+    pub fn get(&self, id: &str) -> &Theme {
+        self.themes
+            .get(id)
+            .or_else(|| self.themes.get(DEFAULT_THEME))
+            .expect("default theme is always there")
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.themes.contains_key(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Theme)> {
+        self.themes.iter().map(|(id, theme)| (id.as_str(), theme))
+    }
+
+    pub fn retain(&mut self, predicate: impl FnMut(&String, &mut Theme) -> bool) {
+        self.themes.retain(predicate);
+    }
+}
+
+impl Default for Themes {
+    fn default() -> Self {
+        Self {
+            themes: BTreeMap::from([(DEFAULT_THEME.to_string(), Theme::default())]),
+        }
+    }
+}
+
+// Themes chosen by the players and the servers
+#[derive(Default)]
+pub struct ThemeChoices {
+    file: JsonFile,
+    choices: Choices,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Choices {
+    users: HashMap<UserId, String>,
+    guilds: HashMap<GuildId, String>,
+}
+
+impl ThemeChoices {
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let file = JsonFile::new(path);
+
+        Self {
+            choices: file.load(),
+            file,
+        }
+    }
+
+    fn save(&mut self) {
+        self.file.save(&self.choices);
+    }
+
+    pub fn set_user(&mut self, user_id: UserId, theme: &str) {
+        self.choices.users.insert(user_id, theme.to_string());
+        self.save();
+    }
+
+    pub fn set_guild(&mut self, guild_id: GuildId, theme: &str) {
+        self.choices.guilds.insert(guild_id, theme.to_string());
+        self.save();
+    }
+
+    // Own theme of the player first, then the one of the server
+    pub fn theme_for(&self, user_id: UserId, guild_id: Option<GuildId>) -> &str {
+        self.choices
+            .users
+            .get(&user_id)
+            .or_else(|| guild_id.and_then(|guild_id| self.choices.guilds.get(&guild_id)))
+            .map_or(DEFAULT_THEME, String::as_str)
+    }
+
+    pub fn guild_theme(&self, guild_id: GuildId) -> &str {
+        self.choices
+            .guilds
+            .get(&guild_id)
+            .map_or(DEFAULT_THEME, String::as_str)
+    }
+}
+
+// Colors are written as "#rrggbb"
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rgb<u8>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    parse_color(&text).ok_or_else(|| D::Error::custom(format!("invalid color {:?}", text)))
+}
+
+fn optional_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Rgb<u8>>, D::Error> {
+    color(deserializer).map(Some)
+}
+
+fn parse_color(text: &str) -> Option<Rgb<u8>> {
+    let hex = text.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let value = u32::from_str_radix(hex, 16).ok()?;
+
+    Some(Rgb([(value >> 16) as u8, (value >> 8) as u8, value as u8]))
+}